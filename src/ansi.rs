@@ -0,0 +1,173 @@
+use std::ops::Range;
+
+use crate::Parsed;
+
+/// Removes the escape sequences a producer run with `--color=always` adds,
+/// returning the plain line along with the spans it colored as matches.
+///
+/// grep, ripgrep and git grep color matches red by default, while ag
+/// highlights them black on yellow (`30;43`), so any text with a red
+/// foreground or any background counts as a match. Other sequences, such as
+/// the `\e[K` grep adds after each color or OSC 8 hyperlinks, are dropped.
+pub fn strip(line: Vec<u8>) -> (Vec<u8>, Vec<Range<usize>>) {
+    if !line.contains(&0x1b) {
+        return (line, vec![]);
+    }
+
+    let mut plain = Vec::with_capacity(line.len());
+    let mut spans: Vec<Range<usize>> = vec![];
+    let mut style = Style::default();
+    let mut i = 0;
+    while i < line.len() {
+        match (line[i], line.get(i + 1)) {
+            (0x1b, Some(b'[')) => {
+                let start = i + 2;
+                let end = line[start..]
+                    .iter()
+                    .position(|b| (0x40..=0x7e).contains(b))
+                    .map_or(line.len(), |end| start + end);
+                if line.get(end) == Some(&b'm') {
+                    style = apply_sgr(&line[start..end], style);
+                }
+                i = end + 1;
+            }
+            (0x1b, Some(b']')) => {
+                // An OSC sequence, ended by BEL or ESC \.
+                let end = (i + 2..line.len()).find(|&j| {
+                    line[j] == 0x07 || (line[j] == 0x1b && line.get(j + 1) == Some(&b'\\'))
+                });
+                i = match end {
+                    Some(end) if line[end] == 0x07 => end + 1,
+                    Some(end) => end + 2,
+                    None => line.len(),
+                };
+            }
+            (0x1b, _) => i += 2,
+            (byte, _) => {
+                if style.red || style.background {
+                    match spans.last_mut() {
+                        Some(span) if span.end == plain.len() => span.end += 1,
+                        _ => spans.push(plain.len()..plain.len() + 1),
+                    }
+                }
+                plain.push(byte);
+                i += 1;
+            }
+        }
+    }
+    (plain, spans)
+}
+
+/// The parts of the current SGR state that mark a match.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Style {
+    red: bool,
+    background: bool,
+}
+
+/// Applies the parameters of an SGR sequence to whether the foreground is
+/// red and whether a background is set.
+fn apply_sgr(params: &[u8], mut style: Style) -> Style {
+    let mut params = params.split(|&b| b == b';');
+    while let Some(param) = params.next() {
+        match param {
+            b"" | b"0" => style = Style::default(),
+            b"39" => style.red = false,
+            b"49" => style.background = false,
+            b"31" | b"91" => style.red = true,
+            b"30" | b"32" | b"33" | b"34" | b"35" | b"36" | b"37" | b"90" | b"92" | b"93"
+            | b"94" | b"95" | b"96" | b"97" => style.red = false,
+            b"40" | b"41" | b"42" | b"43" | b"44" | b"45" | b"46" | b"47" | b"100" | b"101"
+            | b"102" | b"103" | b"104" | b"105" | b"106" | b"107" => style.background = true,
+            // 256-color and truecolor foregrounds and backgrounds.
+            b"38" | b"48" => {
+                let skip = match params.next() {
+                    Some(b"5") => 1,
+                    Some(b"2") => 3,
+                    _ => 0,
+                };
+                for _ in 0..skip {
+                    params.next();
+                }
+                if param == b"38" {
+                    style.red = false;
+                } else {
+                    style.background = true;
+                }
+            }
+            _ => {}
+        }
+    }
+    style
+}
+
+/// Gives a record parsed from a stripped line the producer's match spans,
+/// when its contents are the tail of the line as they are for every
+/// grep-like format, and it has no highlights of its own.
+pub fn reuse_spans<'a>(parsed: Parsed<'a>, line: &[u8], spans: &[Range<usize>]) -> Parsed<'a> {
+    match parsed {
+        Parsed::Record(mut record)
+            if record.highlights.is_empty() && line.ends_with(&record.contents) =>
+        {
+            let offset = line.len() - record.contents.len();
+            record.highlights = spans
+                .iter()
+                .filter(|span| span.end > offset && span.start < line.len())
+                .map(|span| span.start.max(offset) - offset..span.end.min(line.len()) - offset)
+                .collect();
+            Parsed::Record(record)
+        }
+        parsed => parsed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sgr(params: &str) -> Style {
+        apply_sgr(params.as_bytes(), Style::default())
+    }
+
+    #[test]
+    fn grep_match() {
+        let (plain, spans) = strip(b"a.c:1:x\x1b[01;31m\x1b[Kfoo\x1b[m\x1b[Ky".to_vec());
+        assert_eq!(plain, b"a.c:1:xfooy");
+        assert_eq!(spans, vec![7..10]);
+    }
+
+    #[test]
+    fn ag_match() {
+        let line =
+            b"\x1b[1;32ma.c\x1b[0m\x1b[K:\x1b[1;33m1\x1b[0m\x1b[K:x\x1b[30;43mfoo\x1b[0m\x1b[Ky";
+        let (plain, spans) = strip(line.to_vec());
+        assert_eq!(plain, b"a.c:1:xfooy");
+        assert_eq!(spans, vec![7..10]);
+    }
+
+    #[test]
+    fn hyperlinks_are_dropped() {
+        let line = b"\x1b]8;;file:///a.c\x1b\\a.c\x1b]8;;\x07:1:x";
+        assert_eq!(strip(line.to_vec()), (b"a.c:1:x".to_vec(), vec![]));
+    }
+
+    #[test]
+    fn red_foreground() {
+        assert!(sgr("1;31").red);
+        assert!(sgr("91").red);
+        assert!(!sgr("31;32").red);
+        assert!(!sgr("31;0").red);
+        assert!(!sgr("31;39").red);
+        assert!(!sgr("31;38;5;1").red);
+        assert_eq!(sgr("1;32"), Style::default());
+    }
+
+    #[test]
+    fn background() {
+        assert!(sgr("30;43").background);
+        assert!(sgr("48;2;255;255;0").background);
+        assert!(!sgr("43;49").background);
+        // The colors of a 256-color background are not foregrounds.
+        assert!(!sgr("48;5;31").red);
+    }
+}
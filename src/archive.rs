@@ -0,0 +1,45 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Lists the members of an archive, using `unzip` for zip files and `tar`
+/// for everything else. An archive which can't be listed has no members.
+pub fn list(archive: &Path) -> HashSet<Vec<u8>> {
+    let zip = is_zip(archive.as_os_str().to_string_lossy().as_bytes());
+    let listing = match zip {
+        true => Command::new("unzip")
+            .arg("-Z1")
+            .arg(archive)
+            .stderr(Stdio::null())
+            .output(),
+        false => Command::new("tar")
+            .arg("-tf")
+            .arg(archive)
+            .stderr(Stdio::null())
+            .output(),
+    };
+    match listing {
+        Ok(output) if output.status.success() => output
+            .stdout
+            .split(|&b| b == b'\n')
+            .map(|entry| without_dot(entry).to_vec())
+            .collect(),
+        _ => HashSet::new(),
+    }
+}
+
+/// Whether a listing from [`list`] includes the member.
+pub fn contains(listing: &HashSet<Vec<u8>>, member: &[u8]) -> bool {
+    listing.contains(without_dot(member))
+}
+
+fn without_dot(path: &[u8]) -> &[u8] {
+    path.strip_prefix(b"./").unwrap_or(path)
+}
+
+fn is_zip(path: &[u8]) -> bool {
+    let path = path.to_ascii_lowercase();
+    [".zip", ".jar", ".war"]
+        .iter()
+        .any(|ext| path.ends_with(ext.as_bytes()))
+}
@@ -0,0 +1,42 @@
+use regex::bytes::Regex;
+
+use crate::{GrepLike, Kind};
+
+/// Recognizes the notices grep and ripgrep print instead of a line when a
+/// binary file matches:
+///
+/// - GNU grep before 3.5: `Binary file x matches`
+/// - GNU grep 3.5 and later: `grep: x: binary file matches`
+/// - ripgrep: `x: binary file matches (found "\0" byte around offset N)`
+pub struct BinaryParser {
+    legacy: Regex,
+    notice: Regex,
+}
+
+impl BinaryParser {
+    pub fn new() -> BinaryParser {
+        BinaryParser {
+            legacy: Regex::new(r#"(?-u)^Binary file (?P<path>.+) matches$"#).unwrap(),
+            notice: Regex::new(
+                r#"(?-u)^(?:grep: )?(?P<path>.+): (?P<text>binary file matches(?: \(.*\))?)$"#,
+            )
+            .unwrap(),
+        }
+    }
+
+    pub fn parse<'a>(&self, line: &'a [u8]) -> Option<GrepLike<'a>> {
+        let (path, text) = match self.notice.captures(line) {
+            Some(captures) => (captures.name("path")?, captures.name("text")?.as_bytes()),
+            None => (
+                self.legacy.captures(line)?.name("path")?,
+                &b"binary file matches"[..],
+            ),
+        };
+        Some(GrepLike {
+            kind: Kind::Binary,
+            filepath: path.as_bytes().into(),
+            contents: text.into(),
+            ..GrepLike::default()
+        })
+    }
+}
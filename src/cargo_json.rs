@@ -0,0 +1,120 @@
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::{path_to_bytes, GrepLike, Parsed, Severity};
+
+/// One line of cargo's `--message-format=json` output. Only compiler
+/// messages carry locations; artifacts and build-script notices are skipped.
+#[derive(Deserialize)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+enum Message {
+    CompilerMessage {
+        manifest_path: PathBuf,
+        message: Diagnostic,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct Diagnostic {
+    message: String,
+    code: Option<Code>,
+    level: String,
+    spans: Vec<Span>,
+    children: Vec<Diagnostic>,
+}
+
+#[derive(Deserialize)]
+struct Code {
+    code: String,
+}
+
+#[derive(Deserialize)]
+struct Span {
+    file_name: String,
+    line_start: u64,
+    column_start: u64,
+    is_primary: bool,
+    label: Option<String>,
+}
+
+/// Decodes a single line of cargo's JSON message stream.
+///
+/// Each span of a compiler message becomes a record: primary spans carry the
+/// message's own level, secondary spans are notes labelled with the span's
+/// label. Children without spans are kept as `= note: ...` text, the same way
+/// rustc renders them.
+pub fn parse(line: &[u8]) -> serde_json::Result<Vec<Parsed<'static>>> {
+    let mut parsed = vec![];
+    if let Message::CompilerMessage {
+        manifest_path,
+        message,
+    } = serde_json::from_slice(line)?
+    {
+        let manifest_dir = manifest_path.parent().unwrap_or_else(|| Path::new(""));
+        push_diagnostic(&mut parsed, &message, manifest_dir, 0);
+    }
+    Ok(parsed)
+}
+
+fn push_diagnostic(
+    parsed: &mut Vec<Parsed<'static>>,
+    diagnostic: &Diagnostic,
+    manifest_dir: &Path,
+    depth: usize,
+) {
+    let severity = Severity::from_name(&diagnostic.level);
+    if diagnostic.spans.is_empty() {
+        let text = format!("{}: {}", diagnostic.level, diagnostic.message);
+        let text = match depth {
+            0 => severity.style(&text).to_string(),
+            _ => format!("{:width$}= {}", "", text, width = depth * 2),
+        };
+        parsed.push(Parsed::Text(text.into_bytes().into()));
+    }
+
+    for span in &diagnostic.spans {
+        let (severity, contents) = match (span.is_primary, &span.label) {
+            (true, _) => (severity, diagnostic.message.clone()),
+            (false, Some(label)) => (Severity::Note, label.clone()),
+            (false, None) => (Severity::Note, diagnostic.message.clone()),
+        };
+        parsed.push(Parsed::Record(GrepLike {
+            prefix: workspace_root(manifest_dir, &span.file_name).map(Cow::Owned),
+            filepath: span.file_name.clone().into_bytes().into(),
+            row: Some(span.line_start),
+            column: Some(span.column_start),
+            contents: contents.into_bytes().into(),
+            severity: Some(severity),
+            // Secondary spans are notes on the primary one, which carries the code.
+            code: diagnostic
+                .code
+                .as_ref()
+                .filter(|_| span.is_primary)
+                .map(|c| Cow::Owned(c.code.clone())),
+            ..GrepLike::default()
+        }));
+    }
+
+    for child in &diagnostic.children {
+        push_diagnostic(parsed, child, manifest_dir, depth + 1);
+    }
+}
+
+/// Span paths are relative to the workspace root rather than the package, so
+/// look for the nearest ancestor of the manifest directory which contains
+/// the file, falling back to the manifest directory itself. Absolute paths
+/// (e.g. into the registry) need no root.
+fn workspace_root(manifest_dir: &Path, file_name: &str) -> Option<Vec<u8>> {
+    if Path::new(file_name).is_absolute() {
+        return None;
+    }
+    let root = manifest_dir
+        .ancestors()
+        .find(|dir| dir.join(file_name).exists())
+        .unwrap_or(manifest_dir);
+    Some(path_to_bytes(root).into_owned())
+}
@@ -0,0 +1,138 @@
+use unicode_width::UnicodeWidthChar;
+
+/// The unit a column number counts in. Columns are always 1-based.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColumnUnit {
+    /// UTF-8 bytes, as reported by grep, ripgrep and gcc.
+    Byte,
+    /// Unicode scalar values, as reported by rustc.
+    Char,
+    /// UTF-16 code units, as used by LSP and most JavaScript tooling.
+    Utf16,
+    /// Terminal display cells, where wide characters take two.
+    Cell,
+}
+
+impl ColumnUnit {
+    pub const NAMES: &'static [&'static str] = &["byte", "char", "utf16", "cell"];
+
+    pub fn from_name(name: &str) -> Option<ColumnUnit> {
+        match name {
+            "byte" => Some(ColumnUnit::Byte),
+            "char" => Some(ColumnUnit::Char),
+            "utf16" => Some(ColumnUnit::Utf16),
+            "cell" => Some(ColumnUnit::Cell),
+            _ => None,
+        }
+    }
+
+    /// How many units a character occupies. Invalid UTF-8 bytes count as a
+    /// single unit of any kind, as do control characters.
+    fn width(self, c: Option<char>) -> u64 {
+        match (self, c) {
+            (_, None) => 1,
+            (ColumnUnit::Byte, Some(c)) => c.len_utf8() as u64,
+            (ColumnUnit::Char, Some(_)) => 1,
+            (ColumnUnit::Utf16, Some(c)) => c.len_utf16() as u64,
+            (ColumnUnit::Cell, Some(c)) => c.width().unwrap_or(1) as u64,
+        }
+    }
+}
+
+/// Converts a column on `line` between units. A column inside a character
+/// maps to the start of that character, and any columns past the end of the
+/// line carry over one for one.
+pub fn convert(line: &[u8], column: u64, from: ColumnUnit, to: ColumnUnit) -> u64 {
+    if from == to || column == 0 {
+        return column;
+    }
+
+    let target = column - 1;
+    let mut from_offset = 0;
+    let mut to_offset = 0;
+    for c in chars(line) {
+        if from_offset + from.width(c) > target {
+            return to_offset + 1;
+        }
+        from_offset += from.width(c);
+        to_offset += to.width(c);
+    }
+    to_offset + (target - from_offset) + 1
+}
+
+/// The characters of `line`, with `None` for each byte of invalid UTF-8.
+fn chars(line: &[u8]) -> impl Iterator<Item = Option<char>> + '_ {
+    line.utf8_chunks().flat_map(|chunk| {
+        chunk
+            .valid()
+            .chars()
+            .map(Some)
+            .chain(chunk.invalid().iter().map(|_| None))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ColumnUnit::*;
+    use super::*;
+
+    // Each character takes a different number of bytes: 1, 2, 3, 4 and 1.
+    const LINE: &[u8] = "aé中😀b".as_bytes();
+
+    #[test]
+    fn character_starts() {
+        // The starts of é, 中, 😀, b and the end of the line in each unit.
+        let starts = [
+            (Byte, [2, 4, 7, 11, 12]),
+            (Char, [2, 3, 4, 5, 6]),
+            (Utf16, [2, 3, 4, 6, 7]),
+            (Cell, [2, 3, 5, 7, 8]),
+        ];
+        for (from, from_columns) in starts {
+            for (to, to_columns) in starts {
+                for (&column, &expected) in from_columns.iter().zip(&to_columns) {
+                    assert_eq!(
+                        convert(LINE, column, from, to),
+                        expected,
+                        "{:?} {} to {:?}",
+                        from,
+                        column,
+                        to
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn inside_a_character() {
+        assert_eq!(convert(LINE, 5, Byte, Char), 3);
+        assert_eq!(convert(LINE, 10, Byte, Utf16), 4);
+        assert_eq!(convert(LINE, 5, Utf16, Byte), 7);
+        assert_eq!(convert(LINE, 6, Cell, Char), 4);
+    }
+
+    #[test]
+    fn past_the_end() {
+        assert_eq!(convert(LINE, 14, Byte, Char), 8);
+        assert_eq!(convert(LINE, 8, Char, Byte), 14);
+        assert_eq!(convert(LINE, 10, Cell, Utf16), 9);
+        assert_eq!(convert(b"", 3, Char, Byte), 3);
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        // Each invalid byte counts as one unit, including the two bytes of a
+        // truncated 中.
+        let line = b"a\xff\xe4\xb8\xc3\xa9b";
+        assert_eq!(convert(line, 5, Byte, Char), 5);
+        assert_eq!(convert(line, 6, Byte, Char), 5);
+        assert_eq!(convert(line, 6, Char, Byte), 7);
+        assert_eq!(convert(line, 5, Char, Cell), 5);
+    }
+
+    #[test]
+    fn unknown_column() {
+        assert_eq!(convert(LINE, 0, Byte, Char), 0);
+    }
+}
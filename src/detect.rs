@@ -0,0 +1,178 @@
+use regex::bytes::{Captures, Regex};
+
+use crate::diagnostics::DiagnosticParser;
+use crate::diff::DiffParser;
+use crate::grouped::GroupedParser;
+use crate::{bytes_to_path, grep, Format};
+
+/// How many lines of each input are inspected before picking a format.
+pub const SNIFF_LINES: usize = 16;
+
+/// Guesses the format of an input from its first lines, along with the
+/// line grammar to use if it turns out to be grep-like.
+///
+/// Structured formats are recognized from the first line that shows them,
+/// and whole-document JSON reports from a first line that opens a JSON array
+/// or object.
+/// Compiler diagnostics are picked when every grep-like line is one, and
+/// grouped ag or ack output when every line with a row has no path and some
+/// line could be a heading naming the file.
+///
+/// Otherwise the archive grammar is used when every grep-like line names an
+/// archive member, the git-grep grammar when every one carries a revision,
+/// the vimgrep grammar when every one carries a column, and the grep grammar
+/// when they don't. Inputs without any such lines use the quickfix grammar
+/// if any line is a `path|row col column| text` record, the cscope grammar if
+/// every line is a `file function row text` record, the ctags-x grammar of
+/// `global -x` if every line is a `symbol row path text` record, or the
+/// no-row grammar if every line has a path and none is a bare row.
+pub fn detect(lines: &[&[u8]]) -> (Format, &'static str) {
+    let diagnostics = DiagnosticParser::new();
+    let grouped = GroupedParser::new();
+    let grep = Regex::new(grep::grammar("grep").unwrap()).unwrap();
+    let vimgrep = Regex::new(grep::grammar("vimgrep").unwrap()).unwrap();
+    let git_grep = Regex::new(grep::grammar("git-grep").unwrap()).unwrap();
+    let quickfix = Regex::new(grep::grammar("quickfix").unwrap()).unwrap();
+    let ctags_x = Regex::new(grep::grammar("ctags-x").unwrap()).unwrap();
+    let cscope = Regex::new(grep::grammar("cscope").unwrap()).unwrap();
+    let archive = Regex::new(grep::grammar("archive").unwrap()).unwrap();
+    let no_row = Regex::new(grep::grammar("no-row").unwrap()).unwrap();
+
+    let mut grep_lines = 0;
+    let mut vimgrep_lines = 0;
+    let mut git_grep_lines = 0;
+    let mut archive_lines = 0;
+    let mut no_row_lines = 0;
+    let mut cscope_lines = 0;
+    let mut ctags_x_lines = 0;
+    let mut quickfix_lines = 0;
+    let mut grouped_lines = 0;
+    let mut ungrouped_grep_lines = 0;
+    let mut heading_lines = 0;
+    let mut diagnostic_lines = 0;
+    let mut undiagnostic_grep_lines = 0;
+    let mut lines_seen = 0;
+    for line in lines.iter().filter(|line| !line.is_empty()) {
+        lines_seen += 1;
+        if let Some(format) = structured(line, lines_seen == 1) {
+            return (format, "grep");
+        }
+        if diagnostics.recognizes(line) {
+            diagnostic_lines += 1;
+        } else if grep.is_match(line) {
+            undiagnostic_grep_lines += 1;
+        }
+        if grep.is_match(line) {
+            grep_lines += 1;
+        }
+        if vimgrep.is_match(line) {
+            vimgrep_lines += 1;
+        }
+        if git_grep
+            .captures(line)
+            .is_some_and(|c| looks_like_git_grep(&c))
+        {
+            git_grep_lines += 1;
+        }
+        if archive.is_match(line) {
+            archive_lines += 1;
+        }
+        if quickfix.is_match(line) {
+            quickfix_lines += 1;
+        }
+        if ctags_x.is_match(line) {
+            ctags_x_lines += 1;
+        }
+        if cscope.is_match(line) {
+            cscope_lines += 1;
+        }
+        if no_row.is_match(line) {
+            no_row_lines += 1;
+        }
+        if grouped.recognizes(line) {
+            grouped_lines += 1;
+        } else if grep.is_match(line) {
+            ungrouped_grep_lines += 1;
+        } else {
+            heading_lines += 1;
+        }
+    }
+
+    if diagnostic_lines > 0 && undiagnostic_grep_lines == 0 {
+        (Format::Diagnostics, "grep")
+    } else if ungrouped_grep_lines == 0 && grouped_lines > 0 && heading_lines > 0 {
+        (Format::Grouped, "grep")
+    } else if archive_lines > 0 && archive_lines == grep_lines {
+        (Format::Grep, "archive")
+    } else if git_grep_lines > 0 && git_grep_lines == grep_lines {
+        (Format::Grep, "git-grep")
+    } else if vimgrep_lines > 0 && vimgrep_lines == grep_lines {
+        (Format::Grep, "vimgrep")
+    } else if grep_lines == 0 && quickfix_lines > 0 {
+        (Format::Grep, "quickfix")
+    } else if grep_lines == 0 && cscope_lines > 0 && cscope_lines == lines_seen {
+        (Format::Grep, "cscope")
+    } else if grep_lines == 0 && ctags_x_lines > 0 && ctags_x_lines == lines_seen {
+        (Format::Grep, "ctags-x")
+    } else if grep_lines == 0
+        && grouped_lines == 0
+        && no_row_lines > 0
+        && no_row_lines == lines_seen
+    {
+        (Format::Grep, "no-row")
+    } else {
+        (Format::Grep, "grep")
+    }
+}
+
+/// Revisions may contain `/`, so `host:/abs/path` and `dir/prefix:path`
+/// also fit the git-grep grammar. git grep paths are always relative, and a
+/// revision is not a directory on disk.
+fn looks_like_git_grep(captures: &Captures<'_>) -> bool {
+    let revision = captures.name("revision").map_or(&b""[..], |m| m.as_bytes());
+    let path = captures.name("path").map_or(&b""[..], |m| m.as_bytes());
+    let directory = revision.contains(&b'/') && bytes_to_path(revision).is_dir();
+    !path.starts_with(b"/") && !directory
+}
+
+/// Recognizes formats which a single line settles: JSON messages and
+/// reports, diffs and NUL-separated output. `first` is whether this is the
+/// first non-empty line of the input.
+pub fn structured(line: &[u8], first: bool) -> Option<Format> {
+    if line.starts_with(b"{") {
+        match serde_json::from_slice(line) {
+            Ok(serde_json::Value::Object(object)) => {
+                if object.contains_key("reason") {
+                    return Some(Format::CargoJson);
+                } else if object.contains_key("type") {
+                    return Some(Format::RgJson);
+                } else if object.contains_key("results") {
+                    return Some(Format::LintJson);
+                }
+            }
+            // The start of a pretty-printed report.
+            Err(_) if first && starts_json(&line[1..], b'"') => return Some(Format::LintJson),
+            _ => {}
+        }
+    }
+    // Build logs also start lines with `[`, as in `[ 10%] Building`.
+    if line.starts_with(b"[") && first && starts_json(&line[1..], b'{') {
+        return Some(Format::LintJson);
+    }
+    if DiffParser::new(false).recognizes(line) {
+        return Some(Format::Diff);
+    }
+    if line.contains(&b'\0') {
+        return Some(Format::Null);
+    }
+    None
+}
+
+/// Whether what follows an opening bracket or brace is only whitespace, or
+/// starts with `first` or a closing bracket or brace.
+fn starts_json(rest: &[u8], first: u8) -> bool {
+    match rest.iter().find(|b| !b.is_ascii_whitespace()) {
+        None => true,
+        Some(&b) => b == first || b == b']' || b == b'}',
+    }
+}
@@ -0,0 +1,135 @@
+use std::borrow::Cow;
+
+use regex::bytes::Regex;
+
+use crate::{parse_number, write_colored, GrepLike, Parsed, Severity};
+
+/// Parses compiler diagnostics from rustc/cargo and gcc/clang.
+///
+/// rustc reports the message on a header line (`error[E0308]: ...`) and the
+/// location on a following `--> path:row:col` line, so the header is held
+/// until we know whether a location claims it. Secondary `::: path` spans
+/// are reported as notes against the same message. The source snippet and
+/// `= note:` lines that follow are passed through untouched.
+pub struct DiagnosticParser {
+    rustc_header: Regex,
+    rustc_span: Regex,
+    gcc: Regex,
+    included_from: Regex,
+    /// A header that has not been claimed by a `-->` line yet.
+    pending: Option<Header>,
+    /// The header the current `-->` span belonged to, for any `:::` spans.
+    current: Option<Header>,
+}
+
+#[derive(Clone)]
+struct Header {
+    line: Vec<u8>,
+    severity: Severity,
+    code: Option<String>,
+    message: Vec<u8>,
+}
+
+impl DiagnosticParser {
+    pub fn new() -> DiagnosticParser {
+        DiagnosticParser {
+            rustc_header: Regex::new(r#"(?-u)^(error|warning|note|help)(?:\[(\w+)\])?: (.*)$"#)
+                .unwrap(),
+            rustc_span: Regex::new(r#"(?-u)^\s*(-->|:::)\s+(.+?):(\d+):(\d+)$"#).unwrap(),
+            gcc: Regex::new(
+                r#"(?-u)^(.+?):(\d+):(?:(\d+):)? (fatal error|error|warning|note|remark): (.*)$"#,
+            )
+            .unwrap(),
+            included_from: Regex::new(
+                r#"(?-u)^(?:In file included|\s+) from (.+?):(\d+)(?::(\d+))?[:,]$"#,
+            )
+            .unwrap(),
+            pending: None,
+            current: None,
+        }
+    }
+
+    /// Whether the line looks like the start of a diagnostic.
+    pub fn recognizes(&self, line: &[u8]) -> bool {
+        self.rustc_header.is_match(line)
+            || self.rustc_span.is_match(line)
+            || self.gcc.is_match(line)
+    }
+
+    pub fn parse<'a>(&mut self, line: &'a [u8]) -> Vec<Parsed<'a>> {
+        let mut parsed = vec![];
+
+        if let Some(captures) = self.rustc_span.captures(line) {
+            let primary = captures.get(1).unwrap().as_bytes() == b"-->";
+            let header = match primary {
+                true => self.pending.take().or_else(|| self.current.clone()),
+                // rustc never prints codes on notes.
+                false => self.current.clone().map(|header| Header {
+                    severity: Severity::Note,
+                    code: None,
+                    ..header
+                }),
+            };
+            if let Some(header) = header {
+                parsed.push(Parsed::Record(GrepLike {
+                    filepath: captures.get(2).unwrap().as_bytes().into(),
+                    row: parse_number(captures.get(3)),
+                    column: parse_number(captures.get(4)),
+                    contents: header.message.clone().into(),
+                    severity: Some(header.severity),
+                    code: header.code.clone().map(Cow::Owned),
+                    ..GrepLike::default()
+                }));
+                if primary {
+                    self.current = Some(header);
+                }
+                return parsed;
+            }
+        }
+
+        parsed.extend(self.finish());
+
+        if let Some(captures) = self.rustc_header.captures(line) {
+            self.pending = Some(Header {
+                line: line.to_vec(),
+                severity: Severity::from_bytes(captures.get(1).unwrap().as_bytes()),
+                code: captures
+                    .get(2)
+                    .map(|s| String::from_utf8_lossy(s.as_bytes()).into_owned()),
+                message: captures.get(3).unwrap().as_bytes().to_vec(),
+            });
+            self.current = None;
+        } else if let Some(captures) = self.gcc.captures(line) {
+            parsed.push(Parsed::Record(GrepLike {
+                filepath: captures.get(1).unwrap().as_bytes().into(),
+                row: parse_number(captures.get(2)),
+                column: parse_number(captures.get(3)),
+                contents: captures.get(5).unwrap().as_bytes().into(),
+                severity: Some(Severity::from_bytes(captures.get(4).unwrap().as_bytes())),
+                ..GrepLike::default()
+            }));
+        } else if let Some(captures) = self.included_from.captures(line) {
+            parsed.push(Parsed::Record(GrepLike {
+                filepath: captures.get(1).unwrap().as_bytes().into(),
+                row: parse_number(captures.get(2)),
+                column: parse_number(captures.get(3)),
+                contents: Cow::Borrowed(b"included from here"),
+                severity: Some(Severity::Note),
+                ..GrepLike::default()
+            }));
+        } else {
+            parsed.push(Parsed::Text(line.into()));
+        }
+        parsed
+    }
+
+    /// Releases a header that never received a location, e.g. rustc's
+    /// closing `error: aborting due to previous error`.
+    pub fn finish(&mut self) -> Option<Parsed<'static>> {
+        self.pending.take().map(|header| {
+            let mut line = vec![];
+            let _ = write_colored(&mut line, &header.line, |s| header.severity.style(s));
+            Parsed::Text(Cow::Owned(line))
+        })
+    }
+}
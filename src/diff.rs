@@ -0,0 +1,121 @@
+use regex::bytes::Regex;
+
+use crate::{parse_number, GrepLike, Kind, Parsed};
+
+/// Turns a unified diff, as printed by `git diff` or `diff -u`, into records
+/// against the new side of each file.
+///
+/// Each added line becomes a match at its row, and each removed line a
+/// context record at the row it was removed before. With `hunks`, each hunk
+/// instead becomes a single record at its first new row, with the hunk's
+/// section heading as its contents. The `---` and `+++` file headers and
+/// unchanged lines are dropped; anything else outside hunks, such as
+/// `diff --git` lines or commit messages, is passed through.
+pub struct DiffParser {
+    hunk: Regex,
+    hunks: bool,
+    /// Whether paths have git's `a/` and `b/` prefixes.
+    git: bool,
+    old_path: Vec<u8>,
+    path: Vec<u8>,
+    /// The new-side row of the next line in the hunk.
+    row: u64,
+    /// The lines left in the hunk on the old and new side.
+    old_left: u64,
+    new_left: u64,
+}
+
+impl DiffParser {
+    pub fn new(hunks: bool) -> DiffParser {
+        DiffParser {
+            hunk: Regex::new(r#"(?-u)^@@ -\d+(?:,(\d+))? \+(\d+)(?:,(\d+))? @@ ?(.*)$"#).unwrap(),
+            hunks,
+            git: false,
+            old_path: vec![],
+            path: vec![],
+            row: 0,
+            old_left: 0,
+            new_left: 0,
+        }
+    }
+
+    /// Whether the line starts a diff.
+    pub fn recognizes(&self, line: &[u8]) -> bool {
+        line.starts_with(b"diff --git ") || self.hunk.is_match(line)
+    }
+
+    pub fn parse<'a>(&mut self, line: &'a [u8]) -> Vec<Parsed<'a>> {
+        if self.old_left > 0 || self.new_left > 0 {
+            return self.parse_hunk_line(line).into_iter().collect();
+        }
+
+        if line.starts_with(b"diff ") {
+            self.git = line.starts_with(b"diff --git ");
+            return vec![Parsed::Text(line.into())];
+        } else if let Some(path) = line.strip_prefix(b"--- ") {
+            self.old_path = self.header_path(path, b"a/");
+        } else if let Some(path) = line.strip_prefix(b"+++ ") {
+            self.path = match self.header_path(path, b"b/") {
+                path if path == b"/dev/null" => self.old_path.clone(),
+                path => path,
+            };
+        } else if let Some(captures) = self.hunk.captures(line) {
+            self.old_left = parse_number(captures.get(1)).unwrap_or(1);
+            self.row = parse_number(captures.get(2)).unwrap_or(0);
+            self.new_left = parse_number(captures.get(3)).unwrap_or(1);
+            if self.hunks {
+                return vec![Parsed::Record(GrepLike {
+                    filepath: self.path.clone().into(),
+                    row: Some(self.row.max(1)),
+                    contents: captures.get(4).unwrap().as_bytes().into(),
+                    ..GrepLike::default()
+                })];
+            }
+        } else {
+            return vec![Parsed::Text(line.into())];
+        }
+        vec![]
+    }
+
+    fn parse_hunk_line<'a>(&mut self, line: &'a [u8]) -> Option<Parsed<'a>> {
+        let (kind, row) = match line.first() {
+            Some(b'+') => {
+                self.new_left = self.new_left.saturating_sub(1);
+                self.row += 1;
+                (Kind::Match, self.row - 1)
+            }
+            Some(b'-') => {
+                self.old_left = self.old_left.saturating_sub(1);
+                (Kind::Context, self.row)
+            }
+            Some(b'\\') => return None,
+            _ => {
+                self.old_left = self.old_left.saturating_sub(1);
+                self.new_left = self.new_left.saturating_sub(1);
+                self.row += 1;
+                return None;
+            }
+        };
+        if self.hunks {
+            return None;
+        }
+        Some(Parsed::Record(GrepLike {
+            kind,
+            filepath: self.path.clone().into(),
+            row: Some(row.max(1)),
+            contents: line[1..].into(),
+            ..GrepLike::default()
+        }))
+    }
+
+    /// The path from a `---` or `+++` header, without any timestamp after a
+    /// tab or git's side prefix.
+    fn header_path(&self, header: &[u8], side: &[u8]) -> Vec<u8> {
+        let path = header.split(|&b| b == b'\t').next().unwrap_or(header);
+        let path = match self.git {
+            true => path.strip_prefix(side).unwrap_or(path),
+            false => path,
+        };
+        path.to_vec()
+    }
+}
@@ -0,0 +1,71 @@
+use std::borrow::Cow;
+
+use regex::bytes::Regex;
+
+use crate::{bytes_to_path, GrepLike, Parsed};
+
+/// Follows the `make[1]: Entering directory '/abs/dir'` and matching
+/// `Leaving directory` lines of recursive make, so that relative paths
+/// reported in between can be resolved against the directory make was in.
+/// Only the build log formats feed it lines, since grep output can match
+/// these lines in the files it searched.
+pub struct DirectoryStack {
+    directory: Regex,
+    stack: Vec<Vec<u8>>,
+}
+
+impl DirectoryStack {
+    pub fn new() -> DirectoryStack {
+        DirectoryStack {
+            directory: Regex::new(
+                r#"(?-u)^\S+: (Entering|Leaving) directory (?:[`'"]|\xE2\x80\x98)(.*)(?:['"]|\xE2\x80\x99)$"#,
+            )
+            .unwrap(),
+            stack: vec![],
+        }
+    }
+
+    /// Updates the stack if the line enters or leaves a directory, returning
+    /// whether it did.
+    pub fn track(&mut self, line: &[u8]) -> bool {
+        let captures = match self.directory.captures(line) {
+            Some(captures) => captures,
+            None => return false,
+        };
+        let directory = captures.get(2).unwrap().as_bytes();
+        if captures.get(1).unwrap().as_bytes() == b"Entering" {
+            self.stack.push(directory.to_vec());
+        } else if let Some(i) = self.stack.iter().rposition(|entry| entry == directory) {
+            self.stack.truncate(i);
+        }
+        true
+    }
+
+    /// Resolves records with relative paths and no prefix of their own
+    /// against the current directory, if any.
+    pub fn resolve<'a>(&self, parsed: Parsed<'a>) -> Parsed<'a> {
+        let directory = match self.stack.last() {
+            Some(directory) => directory,
+            None => return parsed,
+        };
+        match parsed {
+            Parsed::Record(mut record) => {
+                resolve_record(&mut record, directory);
+                Parsed::Record(record)
+            }
+            Parsed::Located(line, mut locations) => {
+                for location in &mut locations {
+                    resolve_record(&mut location.record, directory);
+                }
+                Parsed::Located(line, locations)
+            }
+            text => text,
+        }
+    }
+}
+
+fn resolve_record(record: &mut GrepLike<'_>, directory: &[u8]) {
+    if record.prefix.is_none() && bytes_to_path(&record.filepath).is_relative() {
+        record.prefix = Some(Cow::Owned(directory.to_vec()));
+    }
+}
@@ -0,0 +1,79 @@
+use regex::bytes::Regex;
+
+use crate::grep::to_record;
+use crate::{GrepLike, Kind};
+
+/// The patterns of vim's default `errorformat`, in the order vim tries them.
+/// The `%-G` patterns for lines to ignore have no `path` group, so lines they
+/// match are passed through as text rather than tried against the patterns
+/// after them. The `Entering directory` patterns are handled by the
+/// directory stack.
+const PATTERNS: &[&str] = &[
+    // %*[^"]"%f"%*\D%l: %m and "%f"%*\D%l: %m
+    r#"(?-u)^[^"]*"(?P<path>[^"]+)"\D*(?P<row>\d+): (?P<text>.*)$"#,
+    // %-G%f:%l: (Each undeclared identifier is reported only once
+    r#"(?-u)^[^:]+:\d+: \(Each undeclared identifier is reported only once"#,
+    // %-G%f:%l: for each function it appears in.)
+    r#"(?-u)^[^:]+:\d+: for each function it appears in\.\)"#,
+    // %-GIn file included from %f:%l:%c:, with or without the column and
+    // with `:`, `,` or nothing after it
+    r#"(?-u)^In file included from [^:]+:\d+(?::\d+)?[:,]?$"#,
+    // %-G%*[ ]from %f:%l:%c, likewise
+    r#"(?-u)^ +from [^:]+:\d+(?::\d+)?[:,]?$"#,
+    // %f:%l:%c:%m
+    r#"(?-u)^(?P<path>[^:]+):(?P<row>\d+):(?P<column>\d+):(?P<text>.*)$"#,
+    // %f(%l):%m
+    r#"(?-u)^(?P<path>.+?)\((?P<row>\d+)\):(?P<text>.*)$"#,
+    // %f:%l:%m
+    r#"(?-u)^(?P<path>[^:]+):(?P<row>\d+):(?P<text>.*)$"#,
+    // "%f"\, line %l%*\D%c%*[^ ] %m
+    r#"(?-u)^"(?P<path>[^"]+)", line (?P<row>\d+)\D*(?P<column>\d+)\S* (?P<text>.*)$"#,
+    // %f|%l| %m
+    r#"(?-u)^(?P<path>[^|]+)\|(?P<row>\d+)\| (?P<text>.*)$"#,
+];
+
+/// Parses error files written for vim's `:cfile` with the default
+/// `errorformat`.
+pub struct ErrorfileParser {
+    patterns: Vec<Regex>,
+}
+
+impl ErrorfileParser {
+    pub fn new() -> ErrorfileParser {
+        ErrorfileParser {
+            patterns: PATTERNS
+                .iter()
+                .map(|pattern| Regex::new(pattern).unwrap())
+                .collect(),
+        }
+    }
+
+    pub fn parse<'a>(&self, line: &'a [u8]) -> Option<GrepLike<'a>> {
+        let captures = self
+            .patterns
+            .iter()
+            .find_map(|pattern| pattern.captures(line))?;
+        to_record(captures, Kind::Match)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ignores_include_chains() {
+        let parser = ErrorfileParser::new();
+        assert!(parser.parse(b"In file included from foo.h:3:4:").is_none());
+        assert!(parser.parse(b"                 from bar.c:12,").is_none());
+    }
+
+    #[test]
+    fn file_line_column() {
+        let record = ErrorfileParser::new().parse(b"foo.c:3:4: error").unwrap();
+        assert_eq!(&*record.filepath, b"foo.c");
+        assert_eq!(record.row, Some(3));
+        assert_eq!(record.column, Some(4));
+        assert_eq!(&*record.contents, b" error");
+    }
+}
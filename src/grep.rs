@@ -0,0 +1,247 @@
+use std::cell::RefCell;
+
+use regex::bytes::{Captures, Regex};
+
+use crate::{bytes_to_path, parse_number, GrepLike, Kind, Severity};
+
+/// Built-in line grammars, by name. A grammar may use the named groups
+/// `prefix`, `revision`, `path`, `member`, `row`, `column`, `offset`,
+/// `function`, `severity`, `code` and `text`, of which only `path` is
+/// required.
+///
+/// An `offset` is a byte offset into the file, as printed by `grep -b`, and
+/// is translated into a row and column. Records with neither a row nor an
+/// offset, as printed by grep without `-n`, are written at row 0 unless
+/// `--recover_rows` finds them. A `member` names a file inside the tar or
+/// zip archive at `path`.
+pub const PRESETS: &[(&str, &str)] = &[
+    (
+        "grep",
+        r#"(?-u)(?:[^:/]+/?(?P<prefix>[^:]+):)?(?P<path>[^:]+)(?::(?P<row>\d+))(?::(?P<column>\d+))?:\s*(?P<text>.*)"#,
+    ),
+    (
+        "vimgrep",
+        r#"(?-u)^(?P<path>[^:]+):(?P<row>\d+):(?P<column>\d+):(?P<text>.*)$"#,
+    ),
+    (
+        "git-grep",
+        r#"(?-u)^(?P<revision>[^:\s]+):(?P<path>[^:]*[^:\d][^:]*):(?P<row>\d+):(?:(?P<column>\d+):)?(?P<text>.*)$"#,
+    ),
+    (
+        "byte-offset",
+        r#"(?-u)^(?P<path>[^:]+):(?:(?P<row>\d+):)?(?P<offset>\d+):(?P<text>.*)$"#,
+    ),
+    (
+        "archive",
+        r#"(?-u)^(?P<path>[^:]+\.(?i:tar|tar\.gz|tgz|tar\.bz2|tbz2|tar\.xz|txz|tar\.zst|zip|jar|war)):(?P<member>[^:]+):(?P<row>\d+):(?:(?P<column>\d+):)?(?P<text>.*)$"#,
+    ),
+    (
+        "cscope",
+        r#"(?-u)^(?P<path>\S+) (?P<function>\S+) (?P<row>\d+)(?: (?P<text>.*))?$"#,
+    ),
+    (
+        "ctags-x",
+        r#"(?-u)^(?P<function>\S+)\s+(?P<row>\d+) (?P<path>\S+)\s+(?P<text>.*)$"#,
+    ),
+    (
+        "quickfix",
+        r#"(?-u)^(?P<path>[^|]+)\|(?:(?P<row>\d+)(?: col (?P<column>\d+))?)?(?: (?P<severity>error|warning|info|note)(?: (?P<code>\d+))?)?\| ?(?P<text>.*)$"#,
+    ),
+    ("no-row", r#"(?-u)^(?P<path>[^:]+):(?P<text>.*)$"#),
+    (
+        "msvc",
+        r#"(?-u)^(?P<path>.+?)\((?P<row>\d+)(?:,(?P<column>\d+))?\)\s*:\s*(?P<text>.*)$"#,
+    ),
+];
+
+/// The `path-row-text` context lines grep emits for `-A`, `-B` and `-C`.
+const GREP_CONTEXT: &str =
+    r#"(?-u)^(?:[^:/]+/?(?P<prefix>[^:]+):)?(?P<path>[^:]+?)-(?P<row>\d+)-(?P<text>.*)$"#;
+
+/// The same for `git grep <rev>`, as `rev:path-row-text`.
+const GIT_GREP_CONTEXT: &str =
+    r#"(?-u)^(?P<revision>[^:\s]+):(?P<path>[^:]+?)-(?P<row>\d+)-(?P<text>.*)$"#;
+
+/// The `-row-` between the path and text of a context line.
+const ROW_SEPARATOR: &str = r#"(?-u)^-(\d+)-"#;
+
+/// Parses lines with a single-line grammar. The `grep` and `git-grep`
+/// presets also recognize context lines.
+pub struct GrepParser {
+    line: Regex,
+    context: Option<Regex>,
+    /// The path of the last match, which context lines around it share.
+    last_path: RefCell<Vec<u8>>,
+    row_separator: Regex,
+}
+
+/// Looks up the grammar of a built-in preset.
+pub fn grammar(name: &str) -> Option<&'static str> {
+    PRESETS
+        .iter()
+        .find(|(preset, _)| *preset == name)
+        .map(|(_, grammar)| *grammar)
+}
+
+impl GrepParser {
+    pub fn preset(name: &str) -> Option<GrepParser> {
+        Some(GrepParser {
+            line: Regex::new(grammar(name)?).unwrap(),
+            context: match name {
+                "grep" => Some(Regex::new(GREP_CONTEXT).unwrap()),
+                "git-grep" => Some(Regex::new(GIT_GREP_CONTEXT).unwrap()),
+                _ => None,
+            },
+            last_path: RefCell::new(vec![]),
+            row_separator: Regex::new(ROW_SEPARATOR).unwrap(),
+        })
+    }
+
+    /// Builds a parser from a user-supplied grammar, which must at least
+    /// capture a `path` group.
+    pub fn with_grammar(grammar: &str) -> Result<GrepParser, String> {
+        let line = Regex::new(grammar).map_err(|e| e.to_string())?;
+        if !line.capture_names().any(|name| name == Some("path")) {
+            return Err("the grammar has no `path` capture group".to_string());
+        }
+        Ok(GrepParser {
+            line,
+            context: None,
+            last_path: RefCell::new(vec![]),
+            row_separator: Regex::new(ROW_SEPARATOR).unwrap(),
+        })
+    }
+
+    pub fn parse<'a>(&self, line: &'a [u8]) -> Option<GrepLike<'a>> {
+        // Filenames such as `2024-01-05-log.txt` also match the context
+        // regex, so a line is only read as context when it is not a match.
+        match self.line.captures(line) {
+            Some(captures) => {
+                let record = to_record(captures, Kind::Match)?;
+                *self.last_path.borrow_mut() = record.filepath.to_vec();
+                Some(record)
+            }
+            None => self.parse_context(line),
+        }
+    }
+
+    /// The context regex ends the path at the first `-<row>-`, which may be
+    /// inside a filename such as `my-2-file.c`. Every later `-<row>-` is
+    /// another possible end, so prefer the one giving the last match's path,
+    /// then one giving a file that exists, before falling back to the first.
+    fn parse_context<'a>(&self, line: &'a [u8]) -> Option<GrepLike<'a>> {
+        let captures = self.context.as_ref()?.captures(line)?;
+        let start = captures.name("path")?.start();
+        let record = to_record(captures, Kind::Context)?;
+
+        let splits: Vec<_> = (start..line.len())
+            .take_while(|&i| line[i] != b':')
+            .filter_map(|i| {
+                let row = self.row_separator.captures(&line[i..])?.get(1)?;
+                Some((&line[start..i], parse_number(Some(row)), i + row.end() + 1))
+            })
+            .collect();
+        let last_path = self.last_path.borrow();
+        let split = splits
+            .iter()
+            .find(|(path, _, _)| !path.is_empty() && *path == last_path.as_slice())
+            .or_else(|| {
+                splits
+                    .iter()
+                    .find(|(path, _, _)| !path.is_empty() && bytes_to_path(path).is_file())
+            });
+        Some(match split {
+            Some(&(path, row, text_start)) => GrepLike {
+                filepath: path.into(),
+                row,
+                contents: line[text_start..].into(),
+                ..record
+            },
+            None => record,
+        })
+    }
+}
+
+pub fn to_record(captures: Captures<'_>, kind: Kind) -> Option<GrepLike<'_>> {
+    Some(GrepLike {
+        kind,
+        prefix: captures.name("prefix").map(|s| s.as_bytes().into()),
+        revision: captures.name("revision").map(|s| s.as_bytes().into()),
+        filepath: captures.name("path")?.as_bytes().into(),
+        member: captures.name("member").map(|s| s.as_bytes().into()),
+        row: parse_number(captures.name("row")),
+        column: parse_number(captures.name("column")),
+        offset: parse_number(captures.name("offset")),
+        function: captures.name("function").map(|s| s.as_bytes().into()),
+        severity: captures
+            .name("severity")
+            .map(|s| Severity::from_bytes(s.as_bytes())),
+        code: captures
+            .name("code")
+            .map(|s| String::from_utf8_lossy(s.as_bytes()).into_owned().into()),
+        contents: captures
+            .name("text")
+            .map_or(&b""[..], |s| s.as_bytes())
+            .into(),
+        ..GrepLike::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> GrepLike<'_> {
+        GrepParser::preset("grep")
+            .unwrap()
+            .parse(line.as_bytes())
+            .unwrap()
+    }
+
+    #[test]
+    fn dated_filename_is_a_match() {
+        let record = parse("2024-01-05-log.txt:3:error here");
+        assert_eq!(record.kind, Kind::Match);
+        assert_eq!(&*record.filepath, b"2024-01-05-log.txt");
+        assert_eq!(record.row, Some(3));
+        assert_eq!(&*record.contents, b"error here");
+    }
+
+    #[test]
+    fn hyphenated_filename_is_a_match() {
+        let record = parse("my-2-file.c:3:x");
+        assert_eq!(record.kind, Kind::Match);
+        assert_eq!(&*record.filepath, b"my-2-file.c");
+        assert_eq!(record.row, Some(3));
+    }
+
+    #[test]
+    fn hyphenated_filename_context_line() {
+        let parser = GrepParser::preset("grep").unwrap();
+        parser.parse(b"my-2-file.c:3:x").unwrap();
+        let record = parser.parse(b"my-2-file.c-4-y").unwrap();
+        assert_eq!(record.kind, Kind::Context);
+        assert_eq!(&*record.filepath, b"my-2-file.c");
+        assert_eq!(record.row, Some(4));
+        assert_eq!(&*record.contents, b"y");
+    }
+
+    #[test]
+    fn context_text_with_row_separator() {
+        let parser = GrepParser::preset("grep").unwrap();
+        parser.parse(b"src/a.rs:3:x").unwrap();
+        let record = parser.parse(b"src/a.rs-4-let a-1-b = 2;").unwrap();
+        assert_eq!(&*record.filepath, b"src/a.rs");
+        assert_eq!(record.row, Some(4));
+        assert_eq!(&*record.contents, b"let a-1-b = 2;");
+    }
+
+    #[test]
+    fn context_line() {
+        let record = parse("src/main.rs-12-    let x = 1;");
+        assert_eq!(record.kind, Kind::Context);
+        assert_eq!(&*record.filepath, b"src/main.rs");
+        assert_eq!(record.row, Some(12));
+        assert_eq!(&*record.contents, b"    let x = 1;");
+    }
+}
@@ -0,0 +1,159 @@
+use std::ops::Range;
+
+use regex::bytes::Regex;
+
+use crate::{parse_number, GrepLike, Kind, Parsed};
+
+/// Parses the grouped output of ag and ack, where the path is printed once
+/// on a heading line and the lines under it carry only rows:
+///
+/// - `ag --ackmate`: a `:path` heading, then `row;col len,col len:text`
+///   lines with 0-based columns, or `row:text` for context
+/// - `ag --group` and `ack`: a bare `path` heading, then `row:text`,
+///   `row:col:text` with `--column`, or `row-text` for context
+///
+/// Groups are separated by blank lines, which end the current heading. A
+/// line shaped like a row is never taken as a heading; without a heading
+/// before it, it is passed through as text.
+pub struct GroupedParser {
+    ackmate: Regex,
+    line: Regex,
+    context: Regex,
+    path: Option<Vec<u8>>,
+    /// Whether the current heading is ackmate's `:path`.
+    ackmate_heading: bool,
+}
+
+impl GroupedParser {
+    pub fn new() -> GroupedParser {
+        GroupedParser {
+            ackmate: Regex::new(r#"(?-u)^(\d+);((?:\d+ \d+,?)*):(.*)$"#).unwrap(),
+            line: Regex::new(r#"(?-u)^(\d+)(?::(\d+))?:(.*)$"#).unwrap(),
+            context: Regex::new(r#"(?-u)^(\d+)-(.*)$"#).unwrap(),
+            path: None,
+            ackmate_heading: false,
+        }
+    }
+
+    /// Whether the line looks like a line under a heading.
+    pub fn recognizes(&self, line: &[u8]) -> bool {
+        self.ackmate.is_match(line) || self.line.is_match(line) || self.context.is_match(line)
+    }
+
+    pub fn parse<'a>(&mut self, line: &'a [u8]) -> Vec<Parsed<'a>> {
+        if line.is_empty() {
+            self.path = None;
+            self.ackmate_heading = false;
+            return vec![Parsed::Text(line.into())];
+        }
+        if let Some(path) = line.strip_prefix(b":") {
+            self.path = Some(path.to_vec());
+            self.ackmate_heading = true;
+            return vec![];
+        }
+        let record = if let Some(captures) = self.ackmate.captures(line) {
+            let highlights: Vec<_> = spans(captures.get(2).unwrap().as_bytes()).collect();
+            GrepLike {
+                row: parse_number(captures.get(1)),
+                column: highlights.first().map(|range| range.start as u64 + 1),
+                contents: captures.get(3).unwrap().as_bytes().into(),
+                highlights,
+                ..GrepLike::default()
+            }
+        } else if let Some(captures) = self.line.captures(line) {
+            if self.ackmate_heading {
+                // ackmate marks matches with `;`, so any other row is context,
+                // and a second number is just text.
+                let row = captures.get(1).unwrap();
+                GrepLike {
+                    kind: Kind::Context,
+                    row: parse_number(Some(row)),
+                    contents: line[row.end() + 1..].into(),
+                    ..GrepLike::default()
+                }
+            } else {
+                GrepLike {
+                    row: parse_number(captures.get(1)),
+                    column: parse_number(captures.get(2)),
+                    contents: captures.get(3).unwrap().as_bytes().into(),
+                    ..GrepLike::default()
+                }
+            }
+        } else if let Some(captures) = self.context.captures(line) {
+            GrepLike {
+                kind: Kind::Context,
+                row: parse_number(captures.get(1)),
+                contents: captures.get(2).unwrap().as_bytes().into(),
+                ..GrepLike::default()
+            }
+        } else {
+            // A heading, possibly for the next file without a blank line
+            // before it.
+            self.path = Some(line.to_vec());
+            self.ackmate_heading = false;
+            return vec![];
+        };
+        // Rows before any heading have no file to belong to.
+        match &self.path {
+            Some(path) => vec![Parsed::Record(GrepLike {
+                filepath: path.clone().into(),
+                ..record
+            })],
+            None => vec![Parsed::Text(line.into())],
+        }
+    }
+}
+
+/// The byte ranges of ackmate's `col len,col len` match list.
+fn spans(list: &[u8]) -> impl Iterator<Item = Range<usize>> + '_ {
+    list.split(|&b| b == b',').filter_map(|span| {
+        let span = std::str::from_utf8(span).ok()?;
+        let (start, len) = span.split_once(' ')?;
+        let start: usize = start.parse().ok()?;
+        Some(start..start + len.parse::<usize>().ok()?)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(lines: &[&str]) -> Vec<(Kind, Option<u64>, Option<u64>, String)> {
+        let mut parser = GroupedParser::new();
+        lines
+            .iter()
+            .flat_map(|line| parser.parse(line.as_bytes()))
+            .filter_map(|parsed| match parsed {
+                Parsed::Record(r) => Some((
+                    r.kind,
+                    r.row,
+                    r.column,
+                    String::from_utf8(r.contents.into_owned()).unwrap(),
+                )),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ackmate_context() {
+        assert_eq!(
+            records(&[":a.c", "1:x 2:y", "2;0 3:foo"]),
+            vec![
+                (Kind::Context, Some(1), None, "x 2:y".to_string()),
+                (Kind::Match, Some(2), Some(1), "foo".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn group_rows() {
+        assert_eq!(
+            records(&["a.c", "1-x", "2:3:foo"]),
+            vec![
+                (Kind::Context, Some(1), None, "x".to_string()),
+                (Kind::Match, Some(2), Some(3), "foo".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,136 @@
+use std::borrow::Cow;
+
+use serde::Deserialize;
+
+use crate::{GrepLike, Parsed, Severity};
+
+/// A whole linter report. Unlike the line-oriented formats, these are a
+/// single JSON document, often pretty-printed over many lines.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Report {
+    /// `eslint --format json`: an array of files with their messages.
+    Eslint(Vec<EslintFile>),
+    /// `semgrep --json`.
+    Semgrep { results: Vec<SemgrepResult> },
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EslintFile {
+    file_path: String,
+    messages: Vec<EslintMessage>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EslintMessage {
+    rule_id: Option<String>,
+    /// 1 for warnings, 2 for errors.
+    severity: u8,
+    message: String,
+    line: Option<u64>,
+    column: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct SemgrepResult {
+    check_id: String,
+    path: String,
+    start: SemgrepPosition,
+    extra: SemgrepExtra,
+}
+
+#[derive(Deserialize)]
+struct SemgrepPosition {
+    line: u64,
+    col: u64,
+}
+
+#[derive(Deserialize)]
+struct SemgrepExtra {
+    message: String,
+    severity: String,
+}
+
+/// Collects the lines of an eslint or semgrep JSON report, which can only be
+/// decoded once the whole input has been read. Clippy's JSON output is
+/// cargo's message stream, which the `cargo-json` format handles.
+pub struct ReportParser {
+    buffer: Vec<u8>,
+}
+
+impl ReportParser {
+    pub fn new() -> ReportParser {
+        ReportParser { buffer: vec![] }
+    }
+
+    pub fn push(&mut self, line: &[u8]) {
+        self.buffer.extend_from_slice(line);
+        self.buffer.push(b'\n');
+    }
+
+    /// Decodes the report into one record per finding. Input which isn't a
+    /// report is passed through as it was.
+    pub fn finish(&mut self) -> Vec<Parsed<'static>> {
+        let buffer = std::mem::take(&mut self.buffer);
+        match serde_json::from_slice(&buffer) {
+            Ok(Report::Eslint(files)) => files
+                .into_iter()
+                .flat_map(|file| {
+                    let path = file.file_path;
+                    file.messages.into_iter().map(move |message| {
+                        record(
+                            &path,
+                            message.line,
+                            message.column,
+                            match message.severity {
+                                2 => Severity::Error,
+                                _ => Severity::Warning,
+                            },
+                            message.rule_id,
+                            message.message,
+                        )
+                    })
+                })
+                .collect(),
+            Ok(Report::Semgrep { results }) => results
+                .into_iter()
+                .map(|result| {
+                    record(
+                        &result.path,
+                        Some(result.start.line),
+                        Some(result.start.col),
+                        Severity::from_name(&result.extra.severity.to_lowercase()),
+                        Some(result.check_id),
+                        result.extra.message,
+                    )
+                })
+                .collect(),
+            Err(_) => buffer
+                .split(|&b| b == b'\n')
+                .filter(|line| !line.is_empty())
+                .map(|line| Parsed::Text(Cow::Owned(line.to_vec())))
+                .collect(),
+        }
+    }
+}
+
+fn record(
+    path: &str,
+    row: Option<u64>,
+    column: Option<u64>,
+    severity: Severity,
+    code: Option<String>,
+    message: String,
+) -> Parsed<'static> {
+    Parsed::Record(GrepLike {
+        filepath: path.as_bytes().to_vec().into(),
+        row,
+        column,
+        contents: message.into_bytes().into(),
+        severity: Some(severity),
+        code: code.map(Cow::Owned),
+        ..GrepLike::default()
+    })
+}
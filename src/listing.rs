@@ -0,0 +1,42 @@
+use regex::bytes::Regex;
+
+use crate::{GrepLike, Kind, Parsed};
+
+/// Parses the file lists printed by `grep -l` and `grep -L`: one path per
+/// line, or several separated by `\0` with `-Z`.
+pub fn parse_files(line: &[u8]) -> Vec<Parsed<'_>> {
+    line.split(|&b| b == b'\0')
+        .filter(|path| !path.is_empty())
+        .map(|path| {
+            Parsed::Record(GrepLike {
+                kind: Kind::Path,
+                filepath: path.into(),
+                ..GrepLike::default()
+            })
+        })
+        .collect()
+}
+
+/// Parses the per-file counts printed by `grep -c`, as `path:count`, or
+/// `path\0count` with `-Z`.
+pub struct CountParser {
+    count: Regex,
+}
+
+impl CountParser {
+    pub fn new() -> CountParser {
+        CountParser {
+            count: Regex::new(r#"(?-u)^(?P<path>.+)[:\x00](?P<count>\d+)$"#).unwrap(),
+        }
+    }
+
+    pub fn parse<'a>(&self, line: &'a [u8]) -> Option<GrepLike<'a>> {
+        let captures = self.count.captures(line)?;
+        Some(GrepLike {
+            kind: Kind::Count,
+            filepath: captures.name("path")?.as_bytes().into(),
+            contents: captures.name("count")?.as_bytes().into(),
+            ..GrepLike::default()
+        })
+    }
+}
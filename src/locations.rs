@@ -0,0 +1,79 @@
+use std::ops::Range;
+
+use regex::bytes::{Captures, Regex};
+
+use crate::{parse_number, GrepLike};
+
+/// A location found inside a line of free text, with the spans of the line
+/// it was read from so that it can be rewritten in place.
+#[derive(Clone, Debug)]
+pub struct Location<'a> {
+    pub record: GrepLike<'a>,
+    pub path: Range<usize>,
+    pub row: Option<Range<usize>>,
+    pub column: Option<Range<usize>>,
+}
+
+/// Finds file locations anywhere inside lines of logs and stack traces:
+///
+/// - `path.ext:row` and `path.ext:row:col`, as in Rust panics
+///   (`at src/foo.rs:12:5`), Java frames (`(Foo.java:12)`) and most test
+///   runners
+/// - Python tracebacks: `File "x.py", line 12`
+pub struct LocationParser {
+    patterns: Vec<Regex>,
+}
+
+impl LocationParser {
+    pub fn new() -> LocationParser {
+        LocationParser {
+            patterns: vec![
+                Regex::new(r#"(?-u)File "(?P<path>[^"]+)", line (?P<row>\d+)"#).unwrap(),
+                Regex::new(
+                    r#"(?-u)(?P<path>[^\s:()"'\[\]<>,]+\.[A-Za-z]\w*):(?P<row>\d+)(?::(?P<column>\d+))?"#,
+                )
+                .unwrap(),
+            ],
+        }
+    }
+
+    /// Returns the locations in the line, in order and without overlaps.
+    pub fn parse<'a>(&self, line: &'a [u8]) -> Vec<Location<'a>> {
+        let mut locations: Vec<_> = self
+            .patterns
+            .iter()
+            .flat_map(|pattern| pattern.captures_iter(line))
+            .filter_map(to_location)
+            // `http://host.com:8080` is not a path.
+            .filter(|location| location.path.start == 0 || line[location.path.start - 1] != b':')
+            .collect();
+        locations.sort_by_key(|location| location.path.start);
+
+        let mut end = 0;
+        locations.retain(|location| {
+            let start = location.path.start;
+            let last = location.column.as_ref().or(location.row.as_ref());
+            let keep = start >= end;
+            if keep {
+                end = last.map_or(location.path.end, |range| range.end);
+            }
+            keep
+        });
+        locations
+    }
+}
+
+fn to_location(captures: Captures<'_>) -> Option<Location<'_>> {
+    let path = captures.name("path")?;
+    Some(Location {
+        record: GrepLike {
+            filepath: path.as_bytes().into(),
+            row: parse_number(captures.name("row")),
+            column: parse_number(captures.name("column")),
+            ..GrepLike::default()
+        },
+        path: path.start()..path.end(),
+        row: captures.name("row").map(|m| m.start()..m.end()),
+        column: captures.name("column").map(|m| m.start()..m.end()),
+    })
+}
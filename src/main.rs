@@ -1,68 +1,637 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::io;
 use std::io::prelude::*;
+use std::io::BufReader;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
 
 use clap::{App, Arg};
-use colored::Colorize;
-use regex::{Regex, RegexBuilder};
+use colored::{ColoredString, Colorize};
+use regex::bytes::{Regex, RegexBuilder};
 
-#[derive(Clone, Copy, Debug)]
+use crate::binary::BinaryParser;
+use crate::columns::ColumnUnit;
+use crate::diagnostics::DiagnosticParser;
+use crate::diff::DiffParser;
+use crate::directories::DirectoryStack;
+use crate::errorfile::ErrorfileParser;
+use crate::grep::GrepParser;
+use crate::grouped::GroupedParser;
+use crate::lint_json::ReportParser;
+use crate::listing::CountParser;
+use crate::locations::{Location, LocationParser};
+use crate::multiline::MultilineGrouper;
+use crate::null::NullParser;
+
+mod ansi;
+mod archive;
+mod binary;
+mod cargo_json;
+mod columns;
+mod detect;
+mod diagnostics;
+mod diff;
+mod directories;
+mod errorfile;
+mod grep;
+mod grouped;
+mod lint_json;
+mod listing;
+mod locations;
+mod multiline;
+mod null;
+mod rg_json;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum Kind {
+    #[default]
+    Match,
+    Context,
+    /// A notice that a binary file matched, with no line to show.
+    Binary,
+    /// A bare path, as listed by `grep -l` and `grep -L`.
+    Path,
+    /// The number of matching lines in a file, as listed by `grep -c`. The
+    /// count is kept as the record's contents.
+    Count,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Severity {
+    Error,
+    Warning,
+    Note,
+    Help,
+}
+
+impl Severity {
+    /// Maps the severity names used by rustc, gcc and clang. Anything
+    /// unrecognized (e.g. clang's `remark`) is treated as a note.
+    fn from_name(name: &str) -> Severity {
+        match name {
+            "error" | "fatal error" => Severity::Error,
+            "warning" => Severity::Warning,
+            "help" => Severity::Help,
+            _ => Severity::Note,
+        }
+    }
+
+    fn from_bytes(name: &[u8]) -> Severity {
+        Severity::from_name(&String::from_utf8_lossy(name))
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+            Severity::Help => "help",
+        }
+    }
+
+    fn style(self, s: &str) -> ColoredString {
+        match self {
+            Severity::Error => s.red().bold(),
+            Severity::Warning => s.yellow().bold(),
+            Severity::Note | Severity::Help => s.cyan().bold(),
+        }
+    }
+}
+
+/// What a parser made of one line of input. Records are by far the most
+/// common variant, so they are not boxed.
+#[allow(clippy::large_enum_variant)]
+enum Parsed<'a> {
+    Record(GrepLike<'a>),
+    /// Text which is passed through as-is.
+    Text(Cow<'a, [u8]>),
+    /// Text with locations inside it, which are rewritten in place.
+    Located(Cow<'a, [u8]>, Vec<Location<'a>>),
+}
+
+impl<'a> Parsed<'a> {
+    fn with_input(self, input: Option<&'a str>) -> Parsed<'a> {
+        match self {
+            Parsed::Record(s) => Parsed::Record(GrepLike { input, ..s }),
+            text => text,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
 struct GrepLike<'a> {
-    prefix: Option<&'a str>,
-    filepath: &'a str,
-    row: Option<&'a str>,
-    column: Option<&'a str>,
-    contents: &'a str,
+    kind: Kind,
+    prefix: Option<Cow<'a, [u8]>>,
+    filepath: Cow<'a, [u8]>,
+    /// The file inside the archive at `filepath`, for archive members.
+    member: Option<Cow<'a, [u8]>>,
+    row: Option<u64>,
+    column: Option<u64>,
+    /// A byte offset into the file, in place of a row and column.
+    offset: Option<u64>,
+    /// The text of the line, or of several lines starting at `row` for a
+    /// multiline match.
+    contents: Cow<'a, [u8]>,
+    /// Byte ranges of `contents` the producer reported as matches. When
+    /// present, these take precedence over the `--highlight` regex.
+    highlights: Vec<Range<usize>>,
+    severity: Option<Severity>,
+    /// A diagnostic code such as rustc's `E0308`.
+    code: Option<Cow<'a, str>>,
+    /// The input file the record was read from, when tagging is enabled.
+    input: Option<&'a str>,
+    /// The tree-ish a `git grep <rev>` match was found in.
+    revision: Option<Cow<'a, [u8]>>,
+    /// The function enclosing the line, as reported by cscope, or the symbol
+    /// defined on it, as reported by `global -x` and `ctags -x`.
+    function: Option<Cow<'a, [u8]>>,
+}
+
+/// Settings which apply to every record written.
+struct Options<'o> {
+    /// The `--prefix` argument.
+    extra_prefix: Option<&'o str>,
+    highlight: Option<Regex>,
+    current_dir: PathBuf,
+    check_exists: Option<ExistsIn>,
+    /// Whether `--check_exists` also looks inside archives for members.
+    check_members: bool,
+    input_columns: ColumnUnit,
+    output_columns: ColumnUnit,
+    /// Whether to look up the row of records that have none on disk.
+    recover_rows: bool,
+    /// The file and row the last row was recovered at, so repeated lines in
+    /// one file resolve to successive rows.
+    recovered: RefCell<Option<(PathBuf, u64)>>,
+    /// The members of each archive checked so far, as listing one means
+    /// reading all of it.
+    archive_members: RefCell<HashMap<PathBuf, HashSet<Vec<u8>>>>,
+    /// Whether each `rev:./path` object checked so far exists, as every
+    /// check runs git.
+    revision_objects: RefCell<HashMap<Vec<u8>, bool>>,
+}
+
+/// Where `--check_exists` looks for a record's file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ExistsIn {
+    WorkTree,
+    /// The record's revision, if it has one, otherwise the working tree.
+    Revision,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    CargoJson,
+    Count,
+    Diagnostics,
+    Diff,
+    Errorfile,
+    Files,
+    Grep,
+    Grouped,
+    LintJson,
+    Locations,
+    Null,
+    RgJson,
+}
+
+impl Format {
+    /// Every accepted `--format`, including `auto` for detection.
+    const NAMES: &'static [&'static str] = &[
+        "auto",
+        "cargo-json",
+        "count",
+        "diagnostics",
+        "diff",
+        "errorfile",
+        "files",
+        "grep",
+        "grouped",
+        "lint-json",
+        "locations",
+        "null",
+        "rg-json",
+    ];
+
+    fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "cargo-json" => Some(Format::CargoJson),
+            "count" => Some(Format::Count),
+            "diagnostics" => Some(Format::Diagnostics),
+            "diff" => Some(Format::Diff),
+            "errorfile" => Some(Format::Errorfile),
+            "files" => Some(Format::Files),
+            "grep" => Some(Format::Grep),
+            "grouped" => Some(Format::Grouped),
+            "lint-json" => Some(Format::LintJson),
+            "locations" => Some(Format::Locations),
+            "null" => Some(Format::Null),
+            "rg-json" => Some(Format::RgJson),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Format::CargoJson => "cargo-json",
+            Format::Count => "count",
+            Format::Diagnostics => "diagnostics",
+            Format::Diff => "diff",
+            Format::Errorfile => "errorfile",
+            Format::Files => "files",
+            Format::Grep => "grep",
+            Format::Grouped => "grouped",
+            Format::LintJson => "lint-json",
+            Format::Locations => "locations",
+            Format::Null => "null",
+            Format::RgJson => "rg-json",
+        }
+    }
 }
 
 impl<'a> GrepLike<'a> {
-    fn write<'s, 'r, 'p>(
-        &self,
-        mut w: impl Write,
-        extra_prefix: Option<&'s str>,
-        highlight: Option<&'r Regex>,
-        current_dir: &'p Path,
-        check_exists: bool,
-    ) -> io::Result<()> {
-        let filepath: PathBuf = match (self.prefix, extra_prefix) {
-            (Some(prefix), Some(extra)) => extra.to_string() + prefix + "/" + self.filepath,
-            (Some(prefix), None) => prefix.to_string() + "/" + self.filepath,
-            (None, Some(extra)) => extra.to_string() + self.filepath,
-            (None, None) => self.filepath.to_string(),
-        }
-        .into();
-        let rel_filepath = pathdiff::diff_paths(&filepath, current_dir).unwrap();
-        if check_exists {
-            if std::fs::File::open(&rel_filepath).is_err() {
-                return Ok(());
+    /// Copies the record out of the line it was parsed from. The input tag
+    /// is not kept, as it is only applied once records are complete.
+    fn into_owned(self) -> GrepLike<'static> {
+        GrepLike {
+            kind: self.kind,
+            prefix: self.prefix.map(|s| Cow::Owned(s.into_owned())),
+            filepath: Cow::Owned(self.filepath.into_owned()),
+            member: self.member.map(|s| Cow::Owned(s.into_owned())),
+            row: self.row,
+            column: self.column,
+            offset: self.offset,
+            contents: Cow::Owned(self.contents.into_owned()),
+            highlights: self.highlights,
+            severity: self.severity,
+            code: self.code.map(|s| Cow::Owned(s.into_owned())),
+            input: None,
+            revision: self.revision.map(|s| Cow::Owned(s.into_owned())),
+            function: self.function.map(|s| Cow::Owned(s.into_owned())),
+        }
+    }
+
+    /// The member of an archive the record is in, if any.
+    fn member(&self) -> Option<&[u8]> {
+        self.member.as_deref()
+    }
+
+    /// Resolves the reported path into one relative to `current_dir`,
+    /// applying the record's own prefix and the `--prefix` argument. For
+    /// archive members this is the path of the archive.
+    fn local_path(&self, extra_prefix: Option<&str>, current_dir: &Path) -> PathBuf {
+        let mut filepath = vec![];
+        if let Some(extra) = extra_prefix {
+            filepath.extend_from_slice(extra.as_bytes());
+        }
+        if let Some(prefix) = &self.prefix {
+            filepath.extend_from_slice(prefix);
+            filepath.push(b'/');
+        }
+        filepath.extend_from_slice(&self.filepath);
+        let filepath = bytes_to_path(&filepath);
+        pathdiff::diff_paths(&current_dir.join(filepath), current_dir).unwrap()
+    }
+
+    fn exists(&self, rel_filepath: &Path, exists_in: ExistsIn, options: &Options<'_>) -> bool {
+        match (&self.revision, exists_in) {
+            (Some(revision), ExistsIn::Revision) => {
+                let mut object = revision.to_vec();
+                object.extend_from_slice(b":./");
+                object.extend_from_slice(&path_to_bytes(rel_filepath));
+                *options
+                    .revision_objects
+                    .borrow_mut()
+                    .entry(object)
+                    .or_insert_with_key(|object| {
+                        Command::new("git")
+                            .args(["cat-file", "-e"])
+                            .arg(bytes_to_path(object))
+                            .stderr(Stdio::null())
+                            .status()
+                            .is_ok_and(|status| status.success())
+                    })
             }
+            _ => std::fs::File::open(rel_filepath).is_ok(),
         }
+    }
+
+    /// The row and column to report, in the output column unit. Archive
+    /// members can't be read from disk, so offsets and rows are not looked
+    /// up for them.
+    fn position(&self, rel_filepath: &Path, options: &Options<'_>) -> (Option<u64>, Option<u64>) {
+        let on_disk = self.member().is_none();
+        let (row, column, unit) = match self.offset.filter(|_| on_disk) {
+            Some(offset) => match offset_to_position(rel_filepath, offset) {
+                Ok((row, column)) => (Some(row), Some(column), ColumnUnit::Byte),
+                Err(_) => (None, None, ColumnUnit::Byte),
+            },
+            None if self.row.is_none() && options.recover_rows && on_disk => {
+                match self.recover_position(rel_filepath, options) {
+                    Some((row, column)) => (Some(row), Some(column), ColumnUnit::Byte),
+                    None => (None, None, ColumnUnit::Byte),
+                }
+            }
+            None => (self.row, self.column, options.input_columns),
+        };
+        let column = match column {
+            Some(column) if unit != options.output_columns => {
+                // Prefer the line on disk, since `contents` may be a message
+                // or only the matched part of the line.
+                let line = row
+                    .filter(|_| on_disk)
+                    .and_then(|row| read_line(rel_filepath, row).ok());
+                let line = line.as_deref().unwrap_or(&self.contents);
+                Some(columns::convert(line, column, unit, options.output_columns))
+            }
+            column => column,
+        };
+        (row, column)
+    }
 
-        write!(
-            w,
-            "{}:{}:{}: ",
-            rel_filepath.to_str().unwrap().yellow(),
-            self.row.unwrap_or("0").blue(),
-            self.column.unwrap_or("0").green(),
-        )?;
-        match highlight {
-            Some(re) => {
-                let mut offset = 0;
-                for caps in re.captures_iter(&self.contents) {
-                    let g = caps.get(0).unwrap();
-                    write!(w, "{}", &self.contents[offset..g.start()])?;
-                    write!(w, "{}", g.as_str().red())?;
-                    offset = g.end();
+    /// Finds the row and byte column of `contents` in the file, searching
+    /// after the row last recovered in the same file first.
+    fn recover_position(&self, rel_filepath: &Path, options: &Options<'_>) -> Option<(u64, u64)> {
+        let mut recovered = options.recovered.borrow_mut();
+        let after = match &*recovered {
+            Some((path, row)) if path == rel_filepath => *row,
+            _ => 0,
+        };
+        let (row, column) = find_line(rel_filepath, &self.contents, after).ok()??;
+        *recovered = Some((rel_filepath.to_path_buf(), row));
+        Some((row, column))
+    }
+
+    fn write(&self, mut w: impl Write, options: &Options<'_>) -> io::Result<()> {
+        let rel_filepath = self.local_path(options.extra_prefix, &options.current_dir);
+        if let Some(exists_in) = options.check_exists {
+            if !self.exists(&rel_filepath, exists_in, options) {
+                return Ok(());
+            }
+            if let Some(member) = self.member().filter(|_| options.check_members) {
+                let mut archive_members = options.archive_members.borrow_mut();
+                let listing = archive_members
+                    .entry(rel_filepath.clone())
+                    .or_insert_with(|| archive::list(&rel_filepath));
+                if !archive::contains(listing, member) {
+                    return Ok(());
                 }
-                writeln!(w, "{}", &self.contents[offset..])?;
             }
-            None => writeln!(w, "{}", self.contents)?,
+        }
+
+        match self.kind {
+            Kind::Binary => {
+                self.write_location(&mut w, &rel_filepath, None)?;
+                write!(w, ": ")?;
+                write_colored(&mut w, &self.contents, |s| s.magenta().italic())?;
+                return writeln!(w);
+            }
+            Kind::Path => {
+                self.write_location(&mut w, &rel_filepath, None)?;
+                return writeln!(w);
+            }
+            Kind::Count => {
+                self.write_location(&mut w, &rel_filepath, None)?;
+                write!(w, ":")?;
+                write_colored(&mut w, &self.contents, |s| s.blue())?;
+                return writeln!(w);
+            }
+            Kind::Match | Kind::Context => {}
+        }
+
+        let ranges: Vec<_> = if self.kind == Kind::Context {
+            vec![]
+        } else if !self.highlights.is_empty() {
+            self.highlights.clone()
+        } else if let Some(re) = &options.highlight {
+            re.find_iter(&self.contents)
+                .map(|m| m.start()..m.end())
+                .collect()
+        } else {
+            vec![]
         };
+
+        // A multiline match is written one physical line at a time, each
+        // with its own location, so that highlights spanning lines are split
+        // at the line boundaries.
+        let (row, column) = self.position(&rel_filepath, options);
+        let mut line_start = 0;
+        for (i, line) in self.contents.split(|&b| b == b'\n').enumerate() {
+            let line_end = line_start + line.len();
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            let position = match i {
+                0 => (row, column),
+                i => (row.map(|row| row + i as u64), column.map(|_| 1)),
+            };
+            self.write_location(&mut w, &rel_filepath, Some(position))?;
+            if let (0, Some(severity)) = (i, self.severity) {
+                let label = match &self.code {
+                    Some(code) => format!("{}[{}]", severity.name(), code),
+                    None => severity.name().to_string(),
+                };
+                write!(w, "{}: ", severity.style(&label))?;
+            }
+            if let (0, Some(function)) = (i, &self.function) {
+                write_colored(&mut w, function, |s| s.magenta())?;
+                write!(w, ": ")?;
+            }
+
+            if self.kind == Kind::Context {
+                write_colored(&mut w, line, |s| s.dimmed())?;
+                writeln!(w)?;
+            } else {
+                let line_ranges = ranges
+                    .iter()
+                    .filter(|r| r.end > line_start && r.start < line_end)
+                    .map(|r| {
+                        r.start.max(line_start) - line_start
+                            ..r.end.min(line_start + line.len()) - line_start
+                    });
+                write_highlighted(&mut w, line, line_ranges)?;
+            }
+            line_start = line_end + 1;
+        }
         Ok(())
     }
+
+    /// Writes the input tag, revision and path of the record, followed by
+    /// the row and column if given.
+    fn write_location(
+        &self,
+        mut w: impl Write,
+        rel_filepath: &Path,
+        position: Option<(Option<u64>, Option<u64>)>,
+    ) -> io::Result<()> {
+        if let Some(input) = self.input {
+            write!(w, "{} ", format!("[{}]", input).magenta())?;
+        }
+        if let Some(revision) = &self.revision {
+            write_colored(&mut w, revision, |s| s.cyan())?;
+            write!(w, " ")?;
+        }
+        write_colored(&mut w, &path_to_bytes(rel_filepath), |s| s.yellow())?;
+        if let Some(member) = self.member() {
+            write!(w, ":")?;
+            write_colored(&mut w, member, |s| s.yellow())?;
+        }
+        if let Some((row, column)) = position {
+            write!(
+                w,
+                ":{}:{}: ",
+                row.unwrap_or(0).to_string().blue(),
+                column.unwrap_or(0).to_string().green(),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes a line of free text with each location in it rewritten in place:
+/// the path is made relative like a record's, and the row and column are
+/// colored. Locations which fail `--check_exists` are left as they were.
+fn write_located(
+    mut w: impl Write,
+    line: &[u8],
+    locations: &[Location<'_>],
+    options: &Options<'_>,
+) -> io::Result<()> {
+    let mut offset = 0;
+    for location in locations {
+        let record = &location.record;
+        let rel_filepath = record.local_path(options.extra_prefix, &options.current_dir);
+        if let Some(exists_in) = options.check_exists {
+            if !record.exists(&rel_filepath, exists_in, options) {
+                continue;
+            }
+        }
+        w.write_all(&line[offset..location.path.start])?;
+        write_colored(&mut w, &path_to_bytes(&rel_filepath), |s| s.yellow())?;
+        offset = location.path.end;
+        if let Some(row) = &location.row {
+            w.write_all(&line[offset..row.start])?;
+            write_colored(&mut w, &line[row.clone()], |s| s.blue())?;
+            offset = row.end;
+        }
+        if let Some(column) = &location.column {
+            w.write_all(&line[offset..column.start])?;
+            write_colored(&mut w, &line[column.clone()], |s| s.green())?;
+            offset = column.end;
+        }
+    }
+    w.write_all(&line[offset..])?;
+    writeln!(w)
+}
+
+/// Translates a 0-based byte offset into a file into a 1-based row and byte
+/// column.
+fn offset_to_position(path: &Path, offset: u64) -> io::Result<(u64, u64)> {
+    let file = BufReader::new(std::fs::File::open(path)?);
+    let mut row = 1;
+    let mut line_start = 0;
+    for (i, byte) in file.bytes().take(offset as usize).enumerate() {
+        if byte? == b'\n' {
+            row += 1;
+            line_start = i as u64 + 1;
+        }
+    }
+    Ok((row, offset - line_start + 1))
+}
+
+/// Finds the first line of a file after row `after` which contains `needle`,
+/// wrapping around to the start of the file, and returns its 1-based row and
+/// the byte column `needle` starts at.
+fn find_line(path: &Path, needle: &[u8], after: u64) -> io::Result<Option<(u64, u64)>> {
+    let file = BufReader::new(std::fs::File::open(path)?);
+    let lines = file.split(b'\n').collect::<io::Result<Vec<_>>>()?;
+    let find = |row: usize| {
+        let line = lines[row].strip_suffix(b"\r").unwrap_or(&lines[row]);
+        let column = match needle.is_empty() {
+            true if line.is_empty() => Some(0),
+            true => None,
+            false => line.windows(needle.len()).position(|w| w == needle),
+        };
+        column.map(|column| (row as u64 + 1, column as u64 + 1))
+    };
+    let after = (after as usize).min(lines.len());
+    Ok((after..lines.len()).chain(0..after).find_map(find))
+}
+
+/// Reads the 1-based `row` of a file, without its line terminator.
+fn read_line(path: &Path, row: u64) -> io::Result<Vec<u8>> {
+    let file = BufReader::new(std::fs::File::open(path)?);
+    let mut line = file
+        .split(b'\n')
+        .nth(row.saturating_sub(1) as usize)
+        .unwrap_or_else(|| Ok(vec![]))?;
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(line)
+}
+
+fn write_highlighted(
+    mut w: impl Write,
+    contents: &[u8],
+    ranges: impl Iterator<Item = Range<usize>>,
+) -> io::Result<()> {
+    let mut offset = 0;
+    for range in ranges {
+        if range.start < offset {
+            continue;
+        }
+        w.write_all(&contents[offset..range.start])?;
+        write_colored(&mut w, &contents[range.clone()], |s| s.red())?;
+        offset = range.end;
+    }
+    w.write_all(&contents[offset..])?;
+    writeln!(w)
+}
+
+/// Writes raw bytes wrapped in the same escapes `colored` would use for a
+/// string, so that non-UTF-8 text can still be styled.
+fn write_colored(
+    mut w: impl Write,
+    bytes: &[u8],
+    style: impl FnOnce(&str) -> ColoredString,
+) -> io::Result<()> {
+    let styled = style("\0").to_string();
+    let (start, end) = styled.split_at(styled.find('\0').unwrap());
+    w.write_all(start.as_bytes())?;
+    w.write_all(bytes)?;
+    w.write_all(&end.as_bytes()[1..])
+}
+
+#[cfg(unix)]
+fn bytes_to_path(bytes: &[u8]) -> PathBuf {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    OsStr::from_bytes(bytes).into()
+}
+
+#[cfg(not(unix))]
+fn bytes_to_path(bytes: &[u8]) -> PathBuf {
+    String::from_utf8_lossy(bytes).into_owned().into()
+}
+
+#[cfg(unix)]
+fn path_to_bytes(path: &Path) -> Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().into()
+}
+
+#[cfg(not(unix))]
+fn path_to_bytes(path: &Path) -> Cow<'_, [u8]> {
+    match path.to_string_lossy() {
+        Cow::Borrowed(s) => s.as_bytes().into(),
+        Cow::Owned(s) => s.into_bytes().into(),
+    }
 }
 
 fn main() {
+    let preset_names: Vec<_> = grep::PRESETS.iter().map(|(name, _)| *name).collect();
     let matches = App::new("grep-wrapper")
         .version("1.0")
         .author("Robert Ying <rbtying@aeturnalus.com>")
@@ -90,46 +659,371 @@ fn main() {
                 .long("check_exists")
                 .help("Include only file paths that exist on disk"),
         )
+        .arg(
+            Arg::with_name("check_members")
+                .long("check_members")
+                .requires("check_exists")
+                .help("With --check_exists, also check that archive members exist in the archive"),
+        )
+        .arg(
+            Arg::with_name("exists_in")
+                .long("exists_in")
+                .value_name("WHERE")
+                .help("Where --check_exists looks for files from git grep <rev>")
+                .possible_values(&["worktree", "revision"])
+                .default_value("worktree"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .short("f")
+                .long("format")
+                .value_name("FORMAT")
+                .help("The format of the input lines, or auto to detect it from the first lines")
+                .possible_values(Format::NAMES)
+                .default_value("auto"),
+        )
+        .arg(
+            Arg::with_name("preset")
+                .long("preset")
+                .value_name("PRESET")
+                .help("A built-in line grammar for the grep format")
+                .possible_values(&preset_names)
+                .default_value("grep"),
+        )
+        .arg(
+            Arg::with_name("grammar")
+                .short("g")
+                .long("grammar")
+                .value_name("GRAMMAR_REGEX")
+                .help(
+                    "A line grammar for the grep format, using the named groups \
+                     prefix, revision, path, row, column, offset and text",
+                )
+                .conflicts_with("preset")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("input_columns")
+                .long("input_columns")
+                .value_name("UNIT")
+                .help("The unit of the column numbers in the input")
+                .possible_values(ColumnUnit::NAMES)
+                .default_value("byte"),
+        )
+        .arg(
+            Arg::with_name("output_columns")
+                .long("output_columns")
+                .value_name("UNIT")
+                .help("The unit of the column numbers to output")
+                .possible_values(ColumnUnit::NAMES)
+                .default_value("byte"),
+        )
+        .arg(
+            Arg::with_name("multiline")
+                .short("U")
+                .long("multiline")
+                .help("Join match lines on consecutive rows of a file into one record"),
+        )
+        .arg(
+            Arg::with_name("diff_hunks")
+                .long("diff_hunks")
+                .help("Report one record per hunk of a diff rather than per changed line"),
+        )
+        .arg(
+            Arg::with_name("recover_rows")
+                .long("recover_rows")
+                .help("Look up the row and column of records without a row in the local file"),
+        )
+        .arg(
+            Arg::with_name("debug_format")
+                .long("debug_format")
+                .help("Report the format detected for each input on stderr"),
+        )
+        .arg(
+            Arg::with_name("tag_input")
+                .short("t")
+                .long("tag_input")
+                .help("Tag each record with the input it was read from"),
+        )
+        .arg(
+            Arg::with_name("input")
+                .value_name("INPUT")
+                .help("Files to read, or - for stdin")
+                .multiple(true)
+                .default_value("-"),
+        )
         .get_matches();
-    let extra_prefix = matches.value_of("prefix");
-    let check_exists = matches.value_of("check_exists").is_some();
-    let highlight_regex = matches.value_of("highlight").map(|h| {
-        RegexBuilder::new(&h)
-            .case_insensitive(true)
-            .build()
-            .unwrap()
-    });
-    let line_regex =
-        Regex::new(r#"(?:[^:/]+/?([^:]+):)?([^:]+)(?::(\d+))(?::(\d+))?:\s*(.*)"#).unwrap();
-
-    let cwd = std::env::current_dir().unwrap();
-
-    for line in std::io::stdin().lock().lines() {
-        match line {
-            Ok(line) => {
-                if let Some(captures) = line_regex.captures(&line) {
-                    let s = GrepLike {
-                        prefix: captures.get(1).map(|s| s.as_str()),
-                        filepath: captures.get(2).unwrap().as_str(),
-                        row: captures.get(3).map(|s| s.as_str()),
-                        column: captures.get(4).map(|s| s.as_str()),
-                        contents: captures.get(5).unwrap().as_str(),
-                    };
+    let options = Options {
+        extra_prefix: matches.value_of("prefix"),
+        highlight: matches
+            .value_of("highlight")
+            .map(|h| RegexBuilder::new(h).case_insensitive(true).build().unwrap()),
+        current_dir: std::env::current_dir().unwrap(),
+        check_exists: match matches.value_of("exists_in") {
+            _ if !matches.is_present("check_exists") => None,
+            Some("revision") => Some(ExistsIn::Revision),
+            _ => Some(ExistsIn::WorkTree),
+        },
+        check_members: matches.is_present("check_members"),
+        input_columns: ColumnUnit::from_name(matches.value_of("input_columns").unwrap()).unwrap(),
+        output_columns: ColumnUnit::from_name(matches.value_of("output_columns").unwrap()).unwrap(),
+        recover_rows: matches.is_present("recover_rows"),
+        recovered: RefCell::new(None),
+        archive_members: RefCell::new(HashMap::new()),
+        revision_objects: RefCell::new(HashMap::new()),
+    };
+    let tag_input = matches.is_present("tag_input");
+    let multiline = matches.is_present("multiline");
+    let diff_hunks = matches.is_present("diff_hunks");
+    let debug_format = matches.is_present("debug_format");
+    // An explicit grammar or preset overrides the one detection picks.
+    let grammar = matches.value_of("grammar");
+    let forced_grep_parser = match grammar {
+        Some(grammar) => Some(GrepParser::with_grammar(grammar).unwrap_or_else(|e| {
+            clap::Error::with_description(
+                &format!("Invalid grammar: {}", e),
+                clap::ErrorKind::InvalidValue,
+            )
+            .exit()
+        })),
+        None if matches.occurrences_of("preset") > 0 => {
+            GrepParser::preset(matches.value_of("preset").unwrap())
+        }
+        None => None,
+    };
+    let null_parser = NullParser::new();
+    let binary_parser = BinaryParser::new();
+    let count_parser = CountParser::new();
+    let location_parser = LocationParser::new();
+    let errorfile_parser = ErrorfileParser::new();
+
+    let forced_format = matches.value_of("format").and_then(Format::from_name);
+
+    let emit = |parsed: Parsed<'_>| match parsed {
+        Parsed::Record(s) => {
+            let _ = s.write(&mut std::io::stdout(), &options);
+        }
+        Parsed::Text(text) => {
+            let mut stdout = io::stdout();
+            let _ = stdout.write_all(&text).and_then(|_| writeln!(stdout));
+        }
+        Parsed::Located(line, locations) => {
+            let _ = write_located(&mut std::io::stdout(), &line, &locations, &options);
+        }
+    };
 
-                    let _ = s.write(
-                        &mut std::io::stdout(),
-                        extra_prefix,
-                        highlight_regex.as_ref(),
-                        &cwd,
-                        check_exists,
-                    );
-                } else {
-                    println!("{}", line);
+    for input in matches.values_of("input").unwrap() {
+        let reader: Box<dyn BufRead> = if input == "-" {
+            Box::new(io::stdin().lock())
+        } else {
+            match std::fs::File::open(input) {
+                Ok(f) => Box::new(BufReader::new(f)),
+                Err(e) => {
+                    eprintln!("{}: {}", input, e);
+                    continue;
                 }
             }
-            Err(e) => {
-                eprintln!("err {:?}", e);
+        };
+        let name = match input {
+            "-" => "(standard input)",
+            input => input,
+        };
+        let tag = if tag_input { Some(name) } else { None };
+        let mut lines = reader.split(b'\n').map(|line| line.map(ansi::strip));
+        // Only detection needs to read ahead, and it stops at the first line
+        // which settles the format so that structured output isn't held up.
+        let mut sniffed: Vec<(Vec<u8>, _)> = vec![];
+        if forced_format.is_none() {
+            for line in lines.by_ref() {
+                let (line, spans) = match line {
+                    Ok(line) => line,
+                    Err(e) => {
+                        eprintln!("err {:?}", e);
+                        continue;
+                    }
+                };
+                let first = sniffed
+                    .iter()
+                    .all(|(line, _): &(Vec<u8>, _)| line.is_empty());
+                let settled = detect::structured(&line, first).is_some();
+                sniffed.push((line, spans));
+                if settled || sniffed.len() == detect::SNIFF_LINES {
+                    break;
+                }
+            }
+        }
+        let (format, preset) = match forced_format {
+            Some(format) => (format, matches.value_of("preset").unwrap()),
+            None => {
+                let lines: Vec<_> = sniffed.iter().map(|(line, _)| line.as_slice()).collect();
+                detect::detect(&lines)
+            }
+        };
+        if debug_format {
+            eprintln!(
+                "{}: {} {} format",
+                name,
+                match forced_format {
+                    Some(_) => "using",
+                    None => "detected",
+                },
+                match format {
+                    Format::Grep if grammar.is_some() => "custom grammar",
+                    Format::Grep if forced_grep_parser.is_some() => {
+                        matches.value_of("preset").unwrap()
+                    }
+                    Format::Grep => preset,
+                    format => format.name(),
+                }
+            );
+        }
+        let detected_grep_parser;
+        let grep_parser = match &forced_grep_parser {
+            Some(grep_parser) => grep_parser,
+            None => {
+                detected_grep_parser = GrepParser::preset(preset).unwrap();
+                &detected_grep_parser
+            }
+        };
+        let mut diagnostic_parser = DiagnosticParser::new();
+        let mut directories = DirectoryStack::new();
+        let mut diff_parser = DiffParser::new(diff_hunks);
+        let mut grouped_parser = GroupedParser::new();
+        let mut report_parser = ReportParser::new();
+        let mut grouper = if multiline {
+            Some(MultilineGrouper::new())
+        } else {
+            None
+        };
+
+        for line in sniffed.into_iter().map(Ok).chain(lines) {
+            match line {
+                Ok((mut line, spans)) => {
+                    if line.last() == Some(&b'\r') {
+                        line.pop();
+                    }
+
+                    let parsed = match format {
+                        // Group separator between non-adjacent context blocks,
+                        // or a removed `-` line in a diff.
+                        _ if line == b"--" && format != Format::Diff => {
+                            vec![Parsed::Text(Cow::Owned(
+                                "--".cyan().to_string().into_bytes(),
+                            ))]
+                        }
+                        // Only build logs carry make's directory notices; in
+                        // grep output they are just text that matched. A
+                        // pending diagnostic header comes out before the notice.
+                        Format::Diagnostics | Format::Errorfile | Format::Locations
+                            if directories.track(&line) =>
+                        {
+                            let mut parsed: Vec<_> =
+                                diagnostic_parser.finish().into_iter().collect();
+                            parsed.push(Parsed::Text(line.as_slice().into()));
+                            parsed
+                        }
+                        Format::CargoJson => cargo_json::parse(&line)
+                            .unwrap_or_else(|_| vec![Parsed::Text(line.as_slice().into())]),
+                        Format::Count => record_or_text(count_parser.parse(&line), &line),
+                        Format::Diagnostics => diagnostic_parser.parse(&line),
+                        Format::Diff => diff_parser.parse(&line),
+                        Format::Errorfile => record_or_text(errorfile_parser.parse(&line), &line),
+                        Format::Files => listing::parse_files(&line),
+                        Format::Grep => {
+                            record_or_binary(grep_parser.parse(&line), &binary_parser, &line)
+                        }
+                        Format::Grouped => grouped_parser.parse(&line),
+                        Format::LintJson => {
+                            report_parser.push(&line);
+                            vec![]
+                        }
+                        Format::Locations => {
+                            let locations = location_parser.parse(&line);
+                            vec![Parsed::Located(line.as_slice().into(), locations)]
+                        }
+                        Format::Null => {
+                            record_or_binary(null_parser.parse(&line), &binary_parser, &line)
+                        }
+                        Format::RgJson => match rg_json::parse(&line) {
+                            Ok(s) => s.map(Parsed::Record).into_iter().collect(),
+                            Err(_) => vec![Parsed::Text(line.as_slice().into())],
+                        },
+                    };
+                    let parsed = parsed.into_iter().map(|p| match options.highlight {
+                        None if !spans.is_empty() => ansi::reuse_spans(p, &line, &spans),
+                        _ => p,
+                    });
+                    let parsed = parsed.map(|p| directories.resolve(p));
+                    let parsed: Vec<_> = match &mut grouper {
+                        Some(grouper) => parsed.flat_map(|p| grouper.push(p)).collect(),
+                        None => parsed.collect(),
+                    };
+                    parsed.into_iter().map(|p| p.with_input(tag)).for_each(emit);
+                }
+                Err(e) => {
+                    eprintln!("err {:?}", e);
+                }
             }
         }
+        let mut parsed: Vec<_> = diagnostic_parser.finish().into_iter().collect();
+        parsed.extend(report_parser.finish());
+        if let Some(grouper) = &mut grouper {
+            parsed = parsed.into_iter().flat_map(|p| grouper.push(p)).collect();
+            parsed.extend(grouper.finish());
+        }
+        parsed.into_iter().map(|p| p.with_input(tag)).for_each(emit);
+    }
+}
+
+/// Parses a captured decimal number such as a row or column.
+fn parse_number(m: Option<regex::bytes::Match<'_>>) -> Option<u64> {
+    std::str::from_utf8(m?.as_bytes()).ok()?.parse().ok()
+}
+
+fn record_or_text<'a>(record: Option<GrepLike<'a>>, line: &'a [u8]) -> Vec<Parsed<'a>> {
+    vec![match record {
+        Some(s) => Parsed::Record(s),
+        None => Parsed::Text(line.into()),
+    }]
+}
+
+/// Falls back to a binary file notice for lines the line grammar didn't
+/// parse. The notices never contain `:<row>:`, while a match can end in
+/// `binary file matches` like any other text.
+fn record_or_binary<'a>(
+    record: Option<GrepLike<'a>>,
+    binary_parser: &BinaryParser,
+    line: &'a [u8],
+) -> Vec<Parsed<'a>> {
+    record_or_text(record.or_else(|| binary_parser.parse(line)), line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_grep(line: &str) -> GrepLike<'_> {
+        let grep_parser = GrepParser::preset("grep").unwrap();
+        let line = line.as_bytes();
+        match record_or_binary(grep_parser.parse(line), &BinaryParser::new(), line).remove(0) {
+            Parsed::Record(record) => record,
+            _ => panic!("not a record"),
+        }
+    }
+
+    #[test]
+    fn match_ending_in_binary_notice() {
+        let record = parse_grep("logs/build.log:7:grep: foo: binary file matches");
+        assert_eq!(record.kind, Kind::Match);
+        assert_eq!(&*record.filepath, b"logs/build.log");
+        assert_eq!(record.row, Some(7));
+        assert_eq!(&*record.contents, b"grep: foo: binary file matches");
+    }
+
+    #[test]
+    fn binary_notice() {
+        let record = parse_grep("grep: foo.bin: binary file matches");
+        assert_eq!(record.kind, Kind::Binary);
+        assert_eq!(&*record.filepath, b"foo.bin");
     }
 }
@@ -0,0 +1,65 @@
+use crate::{GrepLike, Kind, Parsed};
+
+/// Joins match lines which continue one another into a single multiline
+/// record, for producers such as `rg -U` which print each physical line of
+/// a match separately when not using `--json`.
+///
+/// Without offsets there is no telling a multiline match from separate
+/// matches on adjacent lines, so these are joined as well; the highlight
+/// regex then runs over the joined lines, while highlights reported by the
+/// producer are carried over to their place in the joined contents.
+pub struct MultilineGrouper {
+    pending: Option<GrepLike<'static>>,
+}
+
+impl MultilineGrouper {
+    pub fn new() -> MultilineGrouper {
+        MultilineGrouper { pending: None }
+    }
+
+    /// Takes the next parsed item, returning whatever is ready to write.
+    pub fn push<'a>(&mut self, parsed: Parsed<'a>) -> Vec<Parsed<'a>> {
+        if let (Some(pending), Parsed::Record(record)) = (&mut self.pending, &parsed) {
+            if continues(pending, record) {
+                let offset = pending.contents.len() + 1;
+                let contents = pending.contents.to_mut();
+                contents.push(b'\n');
+                contents.extend_from_slice(&record.contents);
+                pending.highlights.extend(
+                    record
+                        .highlights
+                        .iter()
+                        .map(|range| range.start + offset..range.end + offset),
+                );
+                return vec![];
+            }
+        }
+
+        let mut ready: Vec<_> = self.finish().into_iter().collect();
+        match parsed {
+            Parsed::Record(record) if groupable(&record) => {
+                self.pending = Some(record.into_owned());
+            }
+            parsed => ready.push(parsed),
+        }
+        ready
+    }
+
+    pub fn finish(&mut self) -> Option<Parsed<'static>> {
+        self.pending.take().map(Parsed::Record)
+    }
+}
+
+fn groupable(record: &GrepLike<'_>) -> bool {
+    record.kind == Kind::Match && record.row.is_some() && record.severity.is_none()
+}
+
+fn continues(pending: &GrepLike<'_>, record: &GrepLike<'_>) -> bool {
+    let lines = pending.contents.iter().filter(|&&b| b == b'\n').count() as u64 + 1;
+    groupable(record)
+        && record.filepath == pending.filepath
+        && record.member == pending.member
+        && record.prefix == pending.prefix
+        && record.revision == pending.revision
+        && record.row == pending.row.map(|row| row + lines)
+}
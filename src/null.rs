@@ -0,0 +1,38 @@
+use regex::bytes::Regex;
+
+use crate::{parse_number, GrepLike, Kind};
+
+/// Parses output where the filename is terminated by `\0` rather than `:`.
+///
+/// `grep -Z` and `rg --null` only replace the separator after the filename,
+/// while `git grep -z` uses `\0` after every field, so both are accepted for
+/// the remainder of the line. A `-` after the row marks a context line.
+pub struct NullParser {
+    rest: Regex,
+}
+
+impl NullParser {
+    pub fn new() -> NullParser {
+        NullParser {
+            rest: Regex::new(r#"(?-u)^(\d+)(?:[:\x00](\d+))?([:\x00-])(.*)$"#).unwrap(),
+        }
+    }
+
+    pub fn parse<'a>(&self, line: &'a [u8]) -> Option<GrepLike<'a>> {
+        let nul = line.iter().position(|&b| b == b'\0')?;
+        let captures = self.rest.captures(&line[nul + 1..])?;
+        let kind = match captures.get(3).unwrap().as_bytes() {
+            b"-" => Kind::Context,
+            _ => Kind::Match,
+        };
+        Some(GrepLike {
+            kind,
+            prefix: None,
+            filepath: line[..nul].into(),
+            row: parse_number(captures.get(1)),
+            column: parse_number(captures.get(2)),
+            contents: captures.get(4).unwrap().as_bytes().into(),
+            ..GrepLike::default()
+        })
+    }
+}
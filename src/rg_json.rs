@@ -0,0 +1,98 @@
+use std::borrow::Cow;
+
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+
+use crate::{GrepLike, Kind};
+
+/// One message of ripgrep's `--json` output stream.
+#[derive(Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "lowercase")]
+enum Message<'a> {
+    Begin(IgnoredAny),
+    #[serde(borrow)]
+    Match(Lines<'a>),
+    #[serde(borrow)]
+    Context(Lines<'a>),
+    End(IgnoredAny),
+    Summary(IgnoredAny),
+}
+
+/// ripgrep's "arbitrary data": UTF-8 text when possible, base64 otherwise.
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Data<'a> {
+    #[serde(borrow)]
+    Text(Cow<'a, str>),
+    Bytes(String),
+}
+
+impl<'a> Data<'a> {
+    fn into_bytes(self) -> Cow<'a, [u8]> {
+        match self {
+            Data::Text(Cow::Borrowed(text)) => Cow::Borrowed(text.as_bytes()),
+            Data::Text(Cow::Owned(text)) => Cow::Owned(text.into_bytes()),
+            Data::Bytes(encoded) => Cow::Owned(base64::decode(&encoded).unwrap_or_default()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Lines<'a> {
+    #[serde(borrow)]
+    path: Data<'a>,
+    #[serde(borrow)]
+    lines: Data<'a>,
+    line_number: Option<u64>,
+    submatches: Vec<SubMatch>,
+}
+
+#[derive(Deserialize)]
+struct SubMatch {
+    start: usize,
+    end: usize,
+}
+
+/// Decodes a single line of `rg --json` output.
+///
+/// `match` and `context` messages become records; `begin`, `end` and
+/// `summary` carry nothing we render and decode to `None`.
+pub fn parse(line: &[u8]) -> serde_json::Result<Option<GrepLike<'_>>> {
+    let (kind, lines) = match serde_json::from_slice(line)? {
+        Message::Match(lines) => (Kind::Match, lines),
+        Message::Context(lines) => (Kind::Context, lines),
+        Message::Begin(_) | Message::End(_) | Message::Summary(_) => return Ok(None),
+    };
+
+    let contents = trim_newline(lines.lines.into_bytes());
+    let highlights: Vec<_> = lines
+        .submatches
+        .iter()
+        .map(|m| m.start.min(contents.len())..m.end.min(contents.len()))
+        .collect();
+
+    Ok(Some(GrepLike {
+        kind,
+        prefix: None,
+        filepath: lines.path.into_bytes(),
+        row: lines.line_number,
+        column: highlights.first().map(|m| m.start as u64 + 1),
+        contents,
+        highlights,
+        ..GrepLike::default()
+    }))
+}
+
+fn trim_newline(text: Cow<'_, [u8]>) -> Cow<'_, [u8]> {
+    let len = text
+        .iter()
+        .rposition(|&b| b != b'\n' && b != b'\r')
+        .map_or(0, |i| i + 1);
+    match text {
+        Cow::Borrowed(text) => Cow::Borrowed(&text[..len]),
+        Cow::Owned(mut text) => {
+            text.truncate(len);
+            Cow::Owned(text)
+        }
+    }
+}